@@ -1,6 +1,7 @@
 use darkwing_diff::{
-  CompressAlgorithm, DiffAlgorithm, Patch, PatchSet, apply, apply_zip, diff,
-  diff_zip,
+  CompressAlgorithm, DiffAlgorithm, Patch, PatchSet, SignatureOptions, apply,
+  apply_zip, diff, diff_with_signature_options, diff_zip,
+  diff_zip_with_signature_options,
 };
 use std::fs;
 use std::path::Path;
@@ -13,13 +14,20 @@ struct DiffMetrics {
   as_what: AsPatchOrPatchSet,
   diff_algo: DiffAlgorithm,
   compress_algo: CompressAlgorithm,
+  block_size: String,
   diff_time_ms: u128,
   apply_time_ms: u128,
   patch_size_bytes: usize,
   total_second_size: usize,
 }
 
-type Variant = (CompressAlgorithm, DiffAlgorithm, AsPatchOrPatchSet);
+// `block_size` only means anything for `DiffAlgorithm::Rsync020`; `None`
+// lets it auto-tune off each file's length (see
+// `crate::rsync::auto_tune_block_size`), while `Some(n)` pins it so the
+// sweep below can compare a few explicit sizes against the auto-tuned
+// default. Ignored by every other `diff_algo`.
+type Variant =
+  (CompressAlgorithm, DiffAlgorithm, AsPatchOrPatchSet, Option<u32>);
 
 fn get_combinations() -> Vec<Variant> {
   // let compress_algorithms =
@@ -55,21 +63,26 @@ fn get_combinations() -> Vec<Variant> {
   use CompressAlgorithm::*;
   use DiffAlgorithm::*;
 
-  let combinations: Vec<(CompressAlgorithm, DiffAlgorithm, AsPatchOrPatchSet)> = vec![
-    (None, Bidiff1, AsPatch), // this combination is VERY low-performant
+  let combinations: Vec<Variant> = vec![
+    (None, Bidiff1, AsPatch, None), // this combination is VERY low-performant
     // (takes tens of seconds on big files (40MB+))
-    (Zstd, Bidiff1, AsPatch), // this combination is VERY low-performant
+    (Zstd { level: 21 }, Bidiff1, AsPatch, None), // this combination is VERY low-performant
     // (takes tens of seconds on big files (40MB+))
-    (None, Rsync020, AsPatch), // this combination is not so low-performant
+    (None, Rsync020, AsPatch, None), // this combination is not so low-performant
     // (its kinda fast), but it generates big patches (10 times bigger than the
     // AsPatchSet variant)
-    (Zstd, Rsync020, AsPatch), // this combination is not so low-performant
+    (Zstd { level: 21 }, Rsync020, AsPatch, None), // this combination is not so low-performant
     // (its kinda fast), but it generates big patches (10 times bigger than the
     // AsPatchSet variant)
-    (None, Rsync020, AsPatchSet),
-    (None, Bidiff1, AsPatchSet),
-    (Zstd, Rsync020, AsPatchSet),
-    (Zstd, Bidiff1, AsPatchSet),
+    (None, Rsync020, AsPatchSet, None),
+    (None, Bidiff1, AsPatchSet, None),
+    (Zstd { level: 21 }, Rsync020, AsPatchSet, None),
+    (Zstd { level: 21 }, Bidiff1, AsPatchSet, None),
+    // A few pinned block sizes, to compare against Rsync020's auto-tuned
+    // default above instead of just trusting the heuristic.
+    (Zstd { level: 21 }, Rsync020, AsPatchSet, Some(512)),
+    (Zstd { level: 21 }, Rsync020, AsPatchSet, Some(4096)),
+    (Zstd { level: 21 }, Rsync020, AsPatchSet, Some(32768)),
   ];
 
   combinations
@@ -174,10 +187,16 @@ fn measure_diff_roundtrip(
   compress_algo: CompressAlgorithm,
   diff_algo: DiffAlgorithm,
   as_patch_or_patch_set: AsPatchOrPatchSet,
+  block_size: Option<u32>,
   original_path: &str,
   modified_path: &str,
   applied_path: &str,
 ) -> Result<DiffMetrics, Box<dyn std::error::Error>> {
+  let signature_options = block_size.map(|block_size| SignatureOptions {
+    block_size,
+    crypto_hash_size: 16,
+  });
+
   // Measure diff time
   let diff_start = Instant::now();
   let patch: PatchOrPatchSet = match as_patch_or_patch_set {
@@ -186,18 +205,41 @@ fn measure_diff_roundtrip(
       let original = fs::read(original_path)?;
       let modified = fs::read(modified_path)?;
 
-      diff(&original, &modified, diff_algo, compress_algo)
-        .unwrap()
-        .into()
+      match signature_options {
+        Some(signature_options) if diff_algo == DiffAlgorithm::Rsync020 => {
+          diff_with_signature_options(
+            &original,
+            &modified,
+            compress_algo,
+            Some(signature_options),
+          )
+          .unwrap()
+          .into()
+        }
+        _ => diff(&original, &modified, diff_algo, compress_algo)
+          .unwrap()
+          .into(),
+      }
     }
-    AsPatchOrPatchSet::AsPatchSet => diff_zip(
-      original_path.into(),
-      modified_path.into(),
-      diff_algo,
-      compress_algo,
-    )
-    .unwrap()
-    .into(),
+    AsPatchOrPatchSet::AsPatchSet => match signature_options {
+      Some(signature_options) => diff_zip_with_signature_options(
+        original_path.into(),
+        modified_path.into(),
+        diff_algo,
+        compress_algo,
+        Some(signature_options),
+      )
+      .unwrap()
+      .into(),
+      None => diff_zip(
+        original_path.into(),
+        modified_path.into(),
+        diff_algo,
+        compress_algo,
+      )
+      .unwrap()
+      .into(),
+    },
   };
   let diff_time = diff_start.elapsed();
 
@@ -242,6 +284,7 @@ fn measure_diff_roundtrip(
     compress_algo,
     diff_algo,
     as_what: as_patch_or_patch_set,
+    block_size: block_size.map_or("auto".to_string(), |n| n.to_string()),
   })
 }
 
@@ -293,6 +336,7 @@ pub fn main() {
         variant.0,
         variant.1,
         variant.2,
+        variant.3,
         before_file,
         after_file,
         &format!("{}.COPY_FOR_TESTS", after_file),
@@ -356,6 +400,7 @@ pub fn main() {
         variant.0,
         variant.1,
         variant.2,
+        variant.3,
         before_file,
         after_file,
         &format!("{}.COPY_FOR_TESTS", after_file),
@@ -404,6 +449,7 @@ fn find_avg_size_reduction(metrics: Vec<DiffMetrics>, variant: Variant) -> f64 {
       m.compress_algo == variant.0
         && m.diff_algo == variant.1
         && m.as_what == variant.2
+        && m.block_size == variant.3.map_or("auto".to_string(), |n| n.to_string())
     })
     .collect();
 
@@ -426,6 +472,7 @@ fn find_avg_speed(metrics: Vec<DiffMetrics>, variant: Variant) -> f64 {
       m.compress_algo == variant.0
         && m.diff_algo == variant.1
         && m.as_what == variant.2
+        && m.block_size == variant.3.map_or("auto".to_string(), |n| n.to_string())
     })
     .collect();
 
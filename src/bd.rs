@@ -1,8 +1,9 @@
-use std::io::{BufWriter, Write};
+use std::io::{BufWriter, Read, Seek, Write};
 
 use super::*;
 
 use bidiff::DiffParams;
+use crate::compress::StreamingOutcome;
 
 pub(super) struct BidiffDiffMachine;
 
@@ -41,13 +42,16 @@ impl DiffMachine for BidiffDiffMachine {
       patch: compressed_patch,
       compress_algorithm,
       diff_algorithm: DiffAlgorithm::Bidiff1,
-      before_hash: hash(before),
-      after_hash: hash(after),
+      hash_algorithm: crate::hash::DEFAULT_HASH_ALGORITHM,
+      before_hash: crate::hash::hash_with(before, crate::hash::DEFAULT_HASH_ALGORITHM),
+      after_hash: crate::hash::hash_with(after, crate::hash::DEFAULT_HASH_ALGORITHM),
+      block_size: None,
+      encrypted: false,
     })
   }
 
   fn apply(base: &[u8], delta: &Patch) -> Result<Vec<u8>, Error> {
-    let hash_before = hash(base);
+    let hash_before = crate::hash::hash_with(base, delta.hash_algorithm);
 
     if hash_before != delta.before_hash {
       return Err(Error::BeforeHashMismatch);
@@ -72,7 +76,7 @@ impl DiffMachine for BidiffDiffMachine {
       Error::BidiffError(format!("failed to get inner of bidiff reader: {}", e))
     })?;
 
-    let hash_after = hash(&after);
+    let hash_after = crate::hash::hash_with(&after, delta.hash_algorithm);
     if hash_after != delta.after_hash {
       return Err(Error::AfterHashMismatch);
     }
@@ -80,3 +84,200 @@ impl DiffMachine for BidiffDiffMachine {
     Ok(after)
   }
 }
+
+/// Returned by [`StreamingDiffMachine::diff_stream`] in place of a
+/// [`Patch`]: every field a `Patch` has except `patch` itself, which was
+/// written directly to `diff_stream`'s `output` sink as it was produced
+/// instead of being held in memory. Pair these fields with the bytes
+/// written to `output` to reconstruct a `Patch`, e.g. for
+/// [`crate::container`], if one is needed.
+#[derive(Debug, Clone)]
+pub struct StreamedPatchHeader {
+  pub diff_algorithm: DiffAlgorithm,
+  pub compress_algorithm: CompressAlgorithm,
+  pub hash_algorithm: crate::hash::HashAlgorithm,
+  pub before_hash: String,
+  pub after_hash: String,
+  pub block_size: Option<u32>,
+}
+
+/// Streaming counterpart to `DiffMachine` for algorithms whose diff and/or
+/// apply side can pipe bytes through rather than needing a second
+/// full-size result buffer. Only [`BidiffDiffMachine`] implements this:
+/// bidiff needs `before`/`after` fully in memory either way to build its
+/// suffix array (there's no public streaming variant of that construction
+/// to call into), but its patch *output*, and [`bipatch::Reader`]'s apply
+/// output, are both already just a `Write`/`Read`, so compressing the
+/// former as a filter and writing the latter straight to the caller's sink
+/// avoids ever holding a second full-size buffer alongside
+/// `before`/`after`/`base`.
+pub trait StreamingDiffMachine {
+  /// Diffs `before` against `after`, writing the (possibly compressed)
+  /// patch bytes to `output` as they're produced rather than returning
+  /// them as a `Vec<u8>`. `before`/`after` are still read fully into memory
+  /// first - see the trait's doc comment - but their hashes are computed
+  /// incrementally as that happens, in the same pass, rather than via a
+  /// second `hash_with` call over the already-buffered bytes.
+  fn diff_stream<B: Read + Seek, A: Read + Seek, W: Write + 'static>(
+    before: B,
+    after: A,
+    output: W,
+    compress_algorithm: CompressAlgorithm,
+  ) -> Result<StreamedPatchHeader, Error>;
+
+  /// Applies `delta` to `base`, writing the result to `output` as it's
+  /// produced rather than returning it as a `Vec<u8>`. `base` itself is
+  /// read fully into memory (and hashed) before any copy command runs,
+  /// since bidiff's copy commands seek to arbitrary offsets in it; only the
+  /// output side streams. `before_hash`/`after_hash` are still checked;
+  /// `after_hash` only once `output` has seen every byte.
+  fn apply_stream<S: Read + Seek, W: Write>(
+    base: S,
+    delta: &Patch,
+    output: W,
+  ) -> Result<(), Error>;
+}
+
+impl StreamingDiffMachine for BidiffDiffMachine {
+  fn diff_stream<B: Read + Seek, A: Read + Seek, W: Write + 'static>(
+    before: B,
+    after: A,
+    output: W,
+    compress_algorithm: CompressAlgorithm,
+  ) -> Result<StreamedPatchHeader, Error> {
+    let (before_buf, before_hash) =
+      read_to_end_hashing(before, crate::hash::DEFAULT_HASH_ALGORITHM)?;
+    let (after_buf, after_hash) =
+      read_to_end_hashing(after, crate::hash::DEFAULT_HASH_ALGORITHM)?;
+
+    let diff_params = DiffParams::new(SORT_PARTITIONS, Some(SCAN_CHUNK_SIZE))
+      .map_err(|e| {
+      Error::BidiffError(format!("failed to create diff params: {}", e))
+    })?;
+
+    match compress_algorithm.streaming_writer(Box::new(output))? {
+      StreamingOutcome::Streaming(mut writer) => {
+        bidiff::simple_diff_with_params(
+          &before_buf,
+          &after_buf,
+          &mut writer,
+          &diff_params,
+        )
+        .map_err(|e| Error::BidiffError(format!("failed to diff: {}", e)))?;
+        writer.finish()?;
+      }
+      StreamingOutcome::Unavailable(mut output) => {
+        let mut patch = Vec::new();
+        bidiff::simple_diff_with_params(
+          &before_buf,
+          &after_buf,
+          &mut patch,
+          &diff_params,
+        )
+        .map_err(|e| Error::BidiffError(format!("failed to diff: {}", e)))?;
+
+        let compressed_patch = compress_algorithm.compress(&patch)?;
+        output
+          .write_all(&compressed_patch)
+          .map_err(|e| Error::IoError(e.to_string()))?;
+      }
+    }
+
+    Ok(StreamedPatchHeader {
+      diff_algorithm: DiffAlgorithm::Bidiff1,
+      compress_algorithm,
+      hash_algorithm: crate::hash::DEFAULT_HASH_ALGORITHM,
+      before_hash,
+      after_hash,
+      block_size: None,
+    })
+  }
+
+  fn apply_stream<S: Read + Seek, W: Write>(
+    base: S,
+    delta: &Patch,
+    output: W,
+  ) -> Result<(), Error> {
+    assert!(delta.diff_algorithm == DiffAlgorithm::Bidiff1);
+
+    let (base_buf, hash_before) = read_to_end_hashing(base, delta.hash_algorithm)?;
+    if hash_before != delta.before_hash {
+      return Err(Error::BeforeHashMismatch);
+    }
+
+    let patch = delta
+      .compress_algorithm
+      .decompress(delta.patch.as_slice())?;
+
+    let patch_reader = std::io::Cursor::new(patch);
+    let base_cursor = std::io::Cursor::new(base_buf);
+
+    let mut fresh_r =
+      bipatch::Reader::new(patch_reader, base_cursor).map_err(|e| {
+        Error::BidiffError(format!("failed to create bidiff reader: {}", e))
+      })?;
+
+    let mut hashing_output = crate::hash::HashingWriter::new(output, delta.hash_algorithm);
+    std::io::copy(&mut fresh_r, &mut hashing_output)
+      .map_err(|e| Error::BidiffError(format!("failed to copy: {}", e)))?;
+
+    let hash_after = hashing_output.finalize();
+    if hash_after != delta.after_hash {
+      return Err(Error::AfterHashMismatch);
+    }
+
+    Ok(())
+  }
+}
+
+/// Reads `reader` to the end into a `Vec<u8>`, feeding every chunk to an
+/// [`crate::hash::IncrementalHash`] as it's read rather than hashing the
+/// buffer in one call afterwards - used by [`BidiffDiffMachine`]'s
+/// streaming diff/apply so the hash is computed in the same pass as the
+/// (unavoidable, see `StreamingDiffMachine`'s doc comment) buffering.
+fn read_to_end_hashing<R: Read>(
+  mut reader: R,
+  algorithm: crate::hash::HashAlgorithm,
+) -> Result<(Vec<u8>, String), Error> {
+  let mut buf = Vec::new();
+  let mut chunk = [0u8; 64 * 1024];
+  let mut hash = crate::hash::IncrementalHash::new(algorithm);
+
+  loop {
+    let read = reader
+      .read(&mut chunk)
+      .map_err(|e| Error::IoError(e.to_string()))?;
+    if read == 0 {
+      break;
+    }
+    hash.update(&chunk[..read]);
+    buf.extend_from_slice(&chunk[..read]);
+  }
+
+  Ok((buf, hash.finalize()))
+}
+
+/// Same diff [`BidiffDiffMachine::diff`] performs, but streams `before`/
+/// `after`/the resulting patch through [`StreamingDiffMachine::diff_stream`]
+/// instead of taking `&[u8]` and returning a [`Patch`] - see that trait's
+/// doc comment for what is and isn't actually streamed.
+pub fn diff_stream<B: Read + Seek, A: Read + Seek, W: Write + 'static>(
+  before: B,
+  after: A,
+  output: W,
+  compress_algorithm: CompressAlgorithm,
+) -> Result<StreamedPatchHeader, Error> {
+  BidiffDiffMachine::diff_stream(before, after, output, compress_algorithm)
+}
+
+/// Same transform [`BidiffDiffMachine::apply`] performs, but streams `base`/
+/// the result through [`StreamingDiffMachine::apply_stream`] instead of
+/// taking `&[u8]` and returning a `Vec<u8>`. `delta.diff_algorithm` must be
+/// `DiffAlgorithm::Bidiff1`.
+pub fn apply_stream<S: Read + Seek, W: Write>(
+  base: S,
+  delta: &Patch,
+  output: W,
+) -> Result<(), Error> {
+  BidiffDiffMachine::apply_stream(base, delta, output)
+}
@@ -0,0 +1,86 @@
+//! Content-defined chunking (CDC) used by `DiffAlgorithm::Cdc`.
+//!
+//! Splits a file's bytes into variable-length chunks using a rolling buzhash
+//! over a sliding window, so that inserting or removing bytes in the middle
+//! of a file only perturbs the chunks immediately around the edit instead of
+//! shifting every fixed-size block after it. Chunk boundaries are therefore
+//! stable across edits, which is what lets identical chunks be deduplicated
+//! both within a single file and across unrelated files in the archive.
+
+use crate::hash;
+
+/// Target average chunk size: 8 KiB, i.e. a 13-bit mask.
+const AVG_CHUNK_SIZE: usize = 8 * 1024;
+const MASK: u64 = (AVG_CHUNK_SIZE as u64) - 1;
+
+pub(crate) const MIN_CHUNK_SIZE: usize = 2 * 1024;
+pub(crate) const MAX_CHUNK_SIZE: usize = 64 * 1024;
+
+const WINDOW_SIZE: usize = 64;
+
+static BUZHASH_TABLE: [u64; 256] = generate_buzhash_table();
+
+// Deterministic splitmix64-derived table, computed at compile time so chunk
+// boundaries are stable across builds and machines.
+const fn generate_buzhash_table() -> [u64; 256] {
+  let mut table = [0u64; 256];
+  let mut seed: u64 = 0x9E3779B97F4A7C15;
+  let mut i = 0;
+  while i < 256 {
+    seed = seed.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = seed;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^= z >> 31;
+    table[i] = z;
+    i += 1;
+  }
+  table
+}
+
+/// A single content-defined chunk of a larger buffer.
+pub(crate) struct Chunk {
+  /// The chunk's byte range within the buffer it was cut from.
+  pub range: std::ops::Range<usize>,
+  /// Strong content hash of `data[range]`, used to key the chunk in the
+  /// `PatchSet` chunk store and to detect duplicate chunks.
+  pub content_hash: String,
+}
+
+/// Splits `data` into content-defined chunks.
+///
+/// Maintains a buzhash over the trailing `WINDOW_SIZE` bytes and cuts a
+/// boundary whenever `hash & MASK == 0`, giving an average chunk size of
+/// `AVG_CHUNK_SIZE`. `MIN_CHUNK_SIZE`/`MAX_CHUNK_SIZE` bound the result so a
+/// long run of low-entropy bytes (e.g. zero padding) can't produce
+/// pathologically small or large chunks.
+pub(crate) fn chunk(data: &[u8]) -> Vec<Chunk> {
+  if data.is_empty() {
+    return Vec::new();
+  }
+
+  let mut chunks = Vec::new();
+  let mut start = 0;
+  let mut h: u64 = 0;
+
+  for pos in 0..data.len() {
+    h = h.rotate_left(1) ^ BUZHASH_TABLE[data[pos] as usize];
+    if pos + 1 > WINDOW_SIZE {
+      let out_index = pos - WINDOW_SIZE;
+      h ^= BUZHASH_TABLE[data[out_index] as usize].rotate_left((WINDOW_SIZE % 64) as u32);
+    }
+
+    let len = pos + 1 - start;
+    let at_hash_boundary = len >= MIN_CHUNK_SIZE && (h & MASK == 0);
+    let at_end = pos + 1 == data.len();
+
+    if at_hash_boundary || len >= MAX_CHUNK_SIZE || at_end {
+      let range = start..pos + 1;
+      let content_hash = hash(&data[range.clone()]);
+      chunks.push(Chunk { range, content_hash });
+      start = pos + 1;
+    }
+  }
+
+  chunks
+}
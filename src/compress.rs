@@ -3,7 +3,18 @@ use std::io::{Read, Write};
 
 use crate::Error;
 
-const ZSTD_COMPRESSION_LEVEL: i32 = 21;
+/// Default compression level used when constructing `CompressAlgorithm::Zstd`
+/// without picking one explicitly (maximum compression).
+pub const DEFAULT_ZSTD_COMPRESSION_LEVEL: i32 = 21;
+
+const BROTLI_COMPRESSION_LEVEL: u32 = 11;
+const BROTLI_LG_WINDOW_SIZE: u32 = 22;
+
+// Magic byte identifying an LZ4-framed block (see `Lz4Compressor`), chosen
+// so a reader handed the wrong bytes fails fast on the magic check instead
+// of misinterpreting an arbitrary byte as a size prefix.
+const LZ4_FRAME_MAGIC: u8 = 0x4C;
+const LZ4_FRAME_HEADER_LEN: usize = 1 + 4 + 4;
 
 /// Compression algorithms available for patch data.
 ///
@@ -30,7 +41,7 @@ const ZSTD_COMPRESSION_LEVEL: i32 = 21;
 ///     before,
 ///     after,
 ///     DiffAlgorithm::Rsync020,
-///     CompressAlgorithm::Zstd
+///     CompressAlgorithm::Zstd { level: 21 }
 /// )?;
 /// # Ok::<(), darkwing_diff::Error>(())
 /// ```
@@ -54,12 +65,71 @@ pub enum CompressAlgorithm {
   /// - Speed is more important than size
   None,
 
-  /// Zstandard compression with level 21 (maximum compression).
-  /// Use this when:
+  /// Zstandard compression at `level` (1-22; higher is smaller but slower).
+  /// Use [`DEFAULT_ZSTD_COMPRESSION_LEVEL`] for the previous fixed
+  /// behavior. Use this when:
   /// - Minimizing patch size is critical
   /// - Network bandwidth or storage is limited
-  /// - Compression time is not a concern
-  Zstd,
+  /// - The caller wants to tune the speed/ratio tradeoff itself, e.g. a
+  ///   low level for interactive diffing and a high one for distribution
+  ///
+  /// Patches written before `level` was configurable used a fixed level of
+  /// `21`; re-diff rather than reuse an archived patch set from that era,
+  /// since rkyv's derived format has no way to backfill a field that
+  /// didn't exist in the archived bytes.
+  Zstd { level: i32 },
+
+  /// LZ4 compression. Much faster than Zstd or Brotli at both compression
+  /// and decompression, at the cost of a noticeably worse ratio. Use this
+  /// when:
+  /// - Patches are applied on a latency-sensitive path
+  /// - The data doesn't compress well anyway (e.g. already-compressed media)
+  Lz4,
+
+  /// Brotli compression at quality 11. Typically beats Zstd's ratio on
+  /// text-heavy data (source trees, JSON, HTML) at the cost of much slower
+  /// compression; decompression speed is comparable to Zstd. Use this when:
+  /// - The payload is text-heavy and patch size matters more than
+  ///   compression time
+  Brotli,
+
+  /// bzip2 compression at `level` (1-9; higher is smaller but slower).
+  /// Typically beats Zstd's ratio on cold, rarely-applied archival deltas,
+  /// at the cost of being noticeably slower at both compression and
+  /// decompression than every other variant here. Use this when:
+  /// - The patch is written once and applied rarely (cold storage, backups)
+  /// - Ratio matters more than either compression or decompression speed
+  Bzip2 { level: u32 },
+
+  /// gzip compression at `level` (0-9; higher is smaller but slower). Worse
+  /// ratio than Zstd or Brotli, but a DEFLATE-framed format nearly every
+  /// language and platform can decompress without pulling in an extra
+  /// dependency. Use this when:
+  /// - The patch may be decompressed by tooling outside this crate
+  /// - Broad decompressor availability matters more than ratio
+  Gzip { level: u32 },
+
+  /// Raw DEFLATE compression at `level` (0-9; higher is smaller but
+  /// slower), same codec as [`Self::Gzip`] without gzip's header/trailer
+  /// and checksum. Use this when:
+  /// - The broad-availability case for [`Self::Gzip`] applies, but the
+  ///   extra framing bytes aren't worth it (e.g. many small patches)
+  Deflate { level: u32 },
+
+  /// FSST (Fast Static Symbol Table) compression: a symbol table trained
+  /// once across every small entry in a `PatchSet` and stored on the patch
+  /// set itself, rather than per-entry like the other variants here. Beats
+  /// Zstd/LZ4 on archives with hundreds of small, vocabulary-similar files,
+  /// where each stream's own framing/warmup overhead otherwise dominates.
+  /// See [`crate::fsst`].
+  ///
+  /// Unlike every other variant, `compress`/`decompress` can't encode or
+  /// decode this one on their own - the shared table lives outside the
+  /// entry being encoded. `compress`/`decompress` return
+  /// [`Error::FsstError`] for this variant; callers that want FSST go
+  /// through [`crate::fsst::encode`]/[`crate::fsst::decode`] directly with
+  /// the patch set's trained table, the way `diff_zip`/`apply_zip` do.
+  Fsst,
 }
 
 impl std::fmt::Display for CompressAlgorithm {
@@ -71,41 +141,342 @@ impl std::fmt::Display for CompressAlgorithm {
 impl CompressAlgorithm {
   /// Compresses the input data using the selected algorithm.
   pub fn compress(self, input: &[u8]) -> Result<Vec<u8>, Error> {
-    match self {
-      Self::None => Ok(input.to_vec()),
-      Self::Zstd => {
-        let mut encoder =
-          zstd::Encoder::new(Vec::new(), ZSTD_COMPRESSION_LEVEL).map_err(
-            |e| {
-              Error::ZipError(format!("failed to create zstd encoder: {}", e))
-            },
-          )?;
-        encoder
-          .write_all(input)
-          .map_err(|e| Error::ZipError(format!("failed to write: {}", e)))?;
-        Ok(
-          encoder
-            .finish()
-            .map_err(|e| Error::ZipError(format!("failed to finish: {}", e)))?,
-        )
-      }
-    }
+    self.compressor()?.encode(input)
   }
 
   /// Decompresses the input data using the selected algorithm.
   pub(crate) fn decompress(self, input: &[u8]) -> Result<Vec<u8>, Error> {
+    self.compressor()?.decode(input)
+  }
+
+  /// Wraps `output` in a streaming encoder for this algorithm, if one is
+  /// available - see [`CompressingWriter`] and [`Compressor::writer`].
+  /// `diff_stream` uses this to compress bidiff's patch output as it's
+  /// produced instead of buffering the whole patch and calling
+  /// [`Self::compress`] once it's complete. Returns
+  /// [`StreamingOutcome::Unavailable`] with `output` handed back unchanged
+  /// for any algorithm without a streaming encoder, so the caller can fall
+  /// back to the one-shot path with the same writer. `Self::Fsst` has no
+  /// standalone encoder at all and returns [`Error::FsstError`] instead, as
+  /// [`Self::compress`] does.
+  pub(crate) fn streaming_writer(
+    self,
+    output: Box<dyn Write>,
+  ) -> Result<StreamingOutcome, Error> {
+    self.compressor()?.writer(output)
+  }
+
+  fn compressor(self) -> Result<Box<dyn Compressor>, Error> {
     match self {
-      Self::None => Ok(input.to_vec()),
-      Self::Zstd => {
-        let mut output = Vec::new();
-        let mut decoder = zstd::Decoder::new(input).map_err(|e| {
-          Error::ZipError(format!("failed to create zstd decoder: {}", e))
-        })?;
-        decoder
-          .read_to_end(&mut output)
-          .map_err(|e| Error::ZipError(format!("failed to read: {}", e)))?;
-        Ok(output)
-      }
+      Self::None => Ok(Box::new(NoneCompressor)),
+      Self::Zstd { level } => Ok(Box::new(ZstdCompressor { level })),
+      Self::Lz4 => Ok(Box::new(Lz4Compressor)),
+      Self::Brotli => Ok(Box::new(BrotliCompressor)),
+      Self::Bzip2 { level } => Ok(Box::new(Bzip2Compressor { level })),
+      Self::Gzip { level } => Ok(Box::new(GzipCompressor { level })),
+      Self::Deflate { level } => Ok(Box::new(DeflateCompressor { level })),
+      Self::Fsst => Err(Error::FsstError(
+        "CompressAlgorithm::Fsst has no standalone encode/decode - use crate::fsst with the PatchSet's trained table".to_string(),
+      )),
     }
   }
 }
+
+// A streaming encoder's `Write` half, plus a way to flush whatever trailer
+// the format needs once the caller is done writing - `Write` alone can't
+// express that, and most of these encoders (zstd, gzip, deflate, bzip2)
+// need an explicit `finish()` rather than relying on `Drop` to flush.
+pub(crate) trait CompressingWriter: Write {
+  fn finish(self: Box<Self>) -> Result<(), Error>;
+}
+
+/// Result of [`CompressAlgorithm::streaming_writer`]: either a live
+/// streaming encoder wrapping the caller's sink, or that same sink handed
+/// back unused because this algorithm doesn't have one.
+pub(crate) enum StreamingOutcome {
+  Streaming(Box<dyn CompressingWriter>),
+  Unavailable(Box<dyn Write>),
+}
+
+// Encode/decode for a single `CompressAlgorithm` variant. Kept as one
+// method pair per backend (rather than one big match in `compress`/
+// `decompress`) so adding a new algorithm only means adding a new small
+// struct and a `compressor` match arm, not threading a new branch through
+// every call site.
+trait Compressor {
+  fn encode(&self, input: &[u8]) -> Result<Vec<u8>, Error>;
+  fn decode(&self, input: &[u8]) -> Result<Vec<u8>, Error>;
+
+  // Wraps `output` in this algorithm's streaming encoder. Defaults to "no
+  // streaming encoder available", handing `output` straight back unused,
+  // for backends whose format can't be produced as a filter over arbitrary
+  // input (`Lz4`'s framing stores the uncompressed length in its header,
+  // known only once everything has been seen) or that just haven't been
+  // wired up to one yet (`Brotli`).
+  fn writer(&self, output: Box<dyn Write>) -> Result<StreamingOutcome, Error> {
+    Ok(StreamingOutcome::Unavailable(output))
+  }
+}
+
+struct NoneCompressor;
+
+impl Compressor for NoneCompressor {
+  fn encode(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+    Ok(input.to_vec())
+  }
+
+  fn decode(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+    Ok(input.to_vec())
+  }
+
+  fn writer(&self, output: Box<dyn Write>) -> Result<StreamingOutcome, Error> {
+    Ok(StreamingOutcome::Streaming(Box::new(PassthroughWriter(
+      output,
+    ))))
+  }
+}
+
+// `CompressAlgorithm::None`'s streaming encoder: there's nothing to encode,
+// so it's just `output` with a no-op `finish`.
+struct PassthroughWriter(Box<dyn Write>);
+
+impl Write for PassthroughWriter {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    self.0.write(buf)
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    self.0.flush()
+  }
+}
+
+impl CompressingWriter for PassthroughWriter {
+  fn finish(self: Box<Self>) -> Result<(), Error> {
+    Ok(())
+  }
+}
+
+struct ZstdCompressor {
+  level: i32,
+}
+
+impl Compressor for ZstdCompressor {
+  fn encode(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut encoder = zstd::Encoder::new(Vec::new(), self.level)
+      .map_err(|e| Error::ZipError(format!("failed to create zstd encoder: {}", e)))?;
+    encoder
+      .write_all(input)
+      .map_err(|e| Error::ZipError(format!("failed to write: {}", e)))?;
+    Ok(
+      encoder
+        .finish()
+        .map_err(|e| Error::ZipError(format!("failed to finish: {}", e)))?,
+    )
+  }
+
+  fn decode(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut output = Vec::new();
+    let mut decoder = zstd::Decoder::new(input)
+      .map_err(|e| Error::ZipError(format!("failed to create zstd decoder: {}", e)))?;
+    decoder
+      .read_to_end(&mut output)
+      .map_err(|e| Error::ZipError(format!("failed to read: {}", e)))?;
+    Ok(output)
+  }
+
+  fn writer(&self, output: Box<dyn Write>) -> Result<StreamingOutcome, Error> {
+    let encoder = zstd::Encoder::new(output, self.level)
+      .map_err(|e| Error::ZipError(format!("failed to create zstd encoder: {}", e)))?;
+    Ok(StreamingOutcome::Streaming(Box::new(encoder)))
+  }
+}
+
+impl CompressingWriter for zstd::Encoder<'static, Box<dyn Write>> {
+  fn finish(self: Box<Self>) -> Result<(), Error> {
+    (*self)
+      .finish()
+      .map(|_| ())
+      .map_err(|e| Error::ZipError(format!("failed to finish: {}", e)))
+  }
+}
+
+// Frames a raw LZ4 block with a small header (magic byte, compressed size,
+// uncompressed size) so `decode` can size its output buffer exactly instead
+// of guessing or growing it incrementally.
+struct Lz4Compressor;
+
+impl Compressor for Lz4Compressor {
+  fn encode(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+    let compressed = lz4_flex::compress(input);
+
+    let mut out = Vec::with_capacity(LZ4_FRAME_HEADER_LEN + compressed.len());
+    out.push(LZ4_FRAME_MAGIC);
+    out.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+    out.extend_from_slice(&(input.len() as u32).to_le_bytes());
+    out.extend_from_slice(&compressed);
+    Ok(out)
+  }
+
+  fn decode(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+    if input.len() < LZ4_FRAME_HEADER_LEN || input[0] != LZ4_FRAME_MAGIC {
+      return Err(Error::ZipError(
+        "malformed lz4 frame: bad magic byte".to_string(),
+      ));
+    }
+
+    let compressed_len =
+      u32::from_le_bytes(input[1..5].try_into().unwrap()) as usize;
+    let uncompressed_len =
+      u32::from_le_bytes(input[5..9].try_into().unwrap()) as usize;
+
+    let compressed = input
+      .get(LZ4_FRAME_HEADER_LEN..LZ4_FRAME_HEADER_LEN + compressed_len)
+      .ok_or_else(|| Error::ZipError("truncated lz4 frame".to_string()))?;
+
+    lz4_flex::decompress(compressed, uncompressed_len)
+      .map_err(|e| Error::ZipError(format!("failed to decompress: {}", e)))
+  }
+}
+
+struct BrotliCompressor;
+
+impl Compressor for BrotliCompressor {
+  fn encode(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut output = Vec::new();
+    let params = brotli::enc::BrotliEncoderParams {
+      quality: BROTLI_COMPRESSION_LEVEL as i32,
+      lgwin: BROTLI_LG_WINDOW_SIZE as i32,
+      ..Default::default()
+    };
+    brotli::BrotliCompress(&mut &input[..], &mut output, &params)
+      .map_err(|e| Error::ZipError(format!("failed to compress: {}", e)))?;
+    Ok(output)
+  }
+
+  fn decode(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut output = Vec::new();
+    brotli::BrotliDecompress(&mut &input[..], &mut output)
+      .map_err(|e| Error::ZipError(format!("failed to decompress: {}", e)))?;
+    Ok(output)
+  }
+}
+
+struct Bzip2Compressor {
+  level: u32,
+}
+
+impl Compressor for Bzip2Compressor {
+  fn encode(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut encoder =
+      bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::new(self.level));
+    encoder
+      .write_all(input)
+      .map_err(|e| Error::ZipError(format!("failed to write: {}", e)))?;
+    encoder
+      .finish()
+      .map_err(|e| Error::ZipError(format!("failed to finish: {}", e)))
+  }
+
+  fn decode(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut output = Vec::new();
+    bzip2::read::BzDecoder::new(input)
+      .read_to_end(&mut output)
+      .map_err(|e| Error::ZipError(format!("failed to read: {}", e)))?;
+    Ok(output)
+  }
+
+  fn writer(&self, output: Box<dyn Write>) -> Result<StreamingOutcome, Error> {
+    Ok(StreamingOutcome::Streaming(Box::new(
+      bzip2::write::BzEncoder::new(output, bzip2::Compression::new(self.level)),
+    )))
+  }
+}
+
+impl CompressingWriter for bzip2::write::BzEncoder<Box<dyn Write>> {
+  fn finish(self: Box<Self>) -> Result<(), Error> {
+    (*self)
+      .finish()
+      .map(|_| ())
+      .map_err(|e| Error::ZipError(format!("failed to finish: {}", e)))
+  }
+}
+
+struct GzipCompressor {
+  level: u32,
+}
+
+impl Compressor for GzipCompressor {
+  fn encode(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut encoder =
+      flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::new(self.level));
+    encoder
+      .write_all(input)
+      .map_err(|e| Error::ZipError(format!("failed to write: {}", e)))?;
+    encoder
+      .finish()
+      .map_err(|e| Error::ZipError(format!("failed to finish: {}", e)))
+  }
+
+  fn decode(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut output = Vec::new();
+    flate2::read::GzDecoder::new(input)
+      .read_to_end(&mut output)
+      .map_err(|e| Error::ZipError(format!("failed to read: {}", e)))?;
+    Ok(output)
+  }
+
+  fn writer(&self, output: Box<dyn Write>) -> Result<StreamingOutcome, Error> {
+    Ok(StreamingOutcome::Streaming(Box::new(
+      flate2::write::GzEncoder::new(output, flate2::Compression::new(self.level)),
+    )))
+  }
+}
+
+impl CompressingWriter for flate2::write::GzEncoder<Box<dyn Write>> {
+  fn finish(self: Box<Self>) -> Result<(), Error> {
+    (*self)
+      .finish()
+      .map(|_| ())
+      .map_err(|e| Error::ZipError(format!("failed to finish: {}", e)))
+  }
+}
+
+struct DeflateCompressor {
+  level: u32,
+}
+
+impl Compressor for DeflateCompressor {
+  fn encode(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut encoder =
+      flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::new(self.level));
+    encoder
+      .write_all(input)
+      .map_err(|e| Error::ZipError(format!("failed to write: {}", e)))?;
+    encoder
+      .finish()
+      .map_err(|e| Error::ZipError(format!("failed to finish: {}", e)))
+  }
+
+  fn decode(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut output = Vec::new();
+    flate2::read::DeflateDecoder::new(input)
+      .read_to_end(&mut output)
+      .map_err(|e| Error::ZipError(format!("failed to read: {}", e)))?;
+    Ok(output)
+  }
+
+  fn writer(&self, output: Box<dyn Write>) -> Result<StreamingOutcome, Error> {
+    Ok(StreamingOutcome::Streaming(Box::new(
+      flate2::write::DeflateEncoder::new(output, flate2::Compression::new(self.level)),
+    )))
+  }
+}
+
+impl CompressingWriter for flate2::write::DeflateEncoder<Box<dyn Write>> {
+  fn finish(self: Box<Self>) -> Result<(), Error> {
+    (*self)
+      .finish()
+      .map(|_| ())
+      .map_err(|e| Error::ZipError(format!("failed to finish: {}", e)))
+  }
+}
@@ -0,0 +1,574 @@
+//! Compact, versioned binary container for [`PatchSet`], written and read
+//! incrementally instead of going through rkyv's all-at-once archive.
+//!
+//! [`PatchSet::to_bytes`]/[`PatchSet::from_bytes`] are still the right choice
+//! when a patch set comfortably fits in memory: they're a single rkyv call
+//! and the result is zero-copy-readable. This module exists for the case
+//! where it doesn't - [`write_to`] streams operations out one at a time as
+//! they're produced, and [`crate::zip::apply_zip_streaming`] reads them back
+//! one at a time via [`read_header`]/[`read_next_operation`] without ever
+//! holding the whole patch set in memory.
+//!
+//! # Format
+//!
+//! ```text
+//! magic             4 bytes   b"FDPS"
+//! version           1 byte    FORMAT_VERSION
+//! hash_algorithm    1 byte    tag, see `hash_algorithm_tag`
+//! hash_before       bytes     length-prefixed
+//! operations_hash   bytes     length-prefixed
+//! metadata          entries   u32 count, then (path, FileMetadata) each
+//! chunk_store       entries   u32 count, then (content_hash, data) each
+//! fsst_table        1 byte    0 = none, 1 = present, then u32 count and
+//!                              one length-prefixed symbol per entry
+//! operation_count   u32
+//! operations        records   length-prefixed, one per operation
+//! ```
+//!
+//! Every multi-byte integer is little-endian. Bumping `FORMAT_VERSION` is the
+//! intended way to land a breaking change (a new tag, a new field) without
+//! breaking readers of older containers: `read_header` rejects any version
+//! it doesn't recognize instead of misinterpreting the bytes that follow.
+
+use std::io::{Read, Write};
+
+use crate::{
+  Error,
+  compress::CompressAlgorithm,
+  hash::HashAlgorithm,
+  patch::{DiffAlgorithm, FileMetadata, Filename, Operation, Patch, PatchSet},
+};
+
+const MAGIC: &[u8; 4] = b"FDPS";
+const FORMAT_VERSION: u8 = 1;
+
+const TAG_PATCH: u8 = 0;
+const TAG_PUT_FILE: u8 = 1;
+const TAG_DELETE_FILE: u8 = 2;
+const TAG_FILE_STAYS_SAME: u8 = 3;
+const TAG_METADATA_ONLY: u8 = 4;
+const TAG_COPY_FROM: u8 = 5;
+const TAG_CHUNKED: u8 = 6;
+const TAG_MOVE_FILE: u8 = 7;
+const TAG_DELTA_FROM: u8 = 8;
+
+/// Everything from the front of a container except the operation records
+/// themselves, which [`read_next_operation`] reads one at a time so a
+/// streaming consumer never has to materialize all of them at once.
+pub(crate) struct ContainerHeader {
+  pub hash_algorithm: HashAlgorithm,
+  pub hash_before: String,
+  pub operations_hash: String,
+  pub metadata: std::collections::HashMap<Filename, FileMetadata>,
+  pub chunk_store: std::collections::HashMap<String, Vec<u8>>,
+  pub fsst_table: Option<crate::fsst::SymbolTable>,
+  pub operation_count: u32,
+}
+
+fn write_u8<W: Write>(writer: &mut W, value: u8) -> Result<(), Error> {
+  writer
+    .write_all(&[value])
+    .map_err(|e| Error::IoError(e.to_string()))
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> Result<(), Error> {
+  writer
+    .write_all(&value.to_le_bytes())
+    .map_err(|e| Error::IoError(e.to_string()))
+}
+
+fn write_bytes<W: Write>(writer: &mut W, value: &[u8]) -> Result<(), Error> {
+  write_u32(writer, value.len() as u32)?;
+  writer
+    .write_all(value)
+    .map_err(|e| Error::IoError(e.to_string()))
+}
+
+fn write_string<W: Write>(writer: &mut W, value: &str) -> Result<(), Error> {
+  write_bytes(writer, value.as_bytes())
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> Result<u8, Error> {
+  let mut buf = [0u8; 1];
+  reader
+    .read_exact(&mut buf)
+    .map_err(|e| Error::InvalidContainer(e.to_string()))?;
+  Ok(buf[0])
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32, Error> {
+  let mut buf = [0u8; 4];
+  reader
+    .read_exact(&mut buf)
+    .map_err(|e| Error::InvalidContainer(e.to_string()))?;
+  Ok(u32::from_le_bytes(buf))
+}
+
+fn read_bytes<R: Read>(reader: &mut R) -> Result<Vec<u8>, Error> {
+  let len = read_u32(reader)? as usize;
+  let mut buf = vec![0u8; len];
+  reader
+    .read_exact(&mut buf)
+    .map_err(|e| Error::InvalidContainer(e.to_string()))?;
+  Ok(buf)
+}
+
+fn read_string<R: Read>(reader: &mut R) -> Result<String, Error> {
+  String::from_utf8(read_bytes(reader)?)
+    .map_err(|e| Error::InvalidContainer(e.to_string()))
+}
+
+fn hash_algorithm_tag(algorithm: HashAlgorithm) -> u8 {
+  match algorithm {
+    HashAlgorithm::Md5 => 0,
+    HashAlgorithm::Blake3 => 1,
+    HashAlgorithm::Xxh3 => 2,
+  }
+}
+
+fn hash_algorithm_from_tag(tag: u8) -> Result<HashAlgorithm, Error> {
+  match tag {
+    0 => Ok(HashAlgorithm::Md5),
+    1 => Ok(HashAlgorithm::Blake3),
+    2 => Ok(HashAlgorithm::Xxh3),
+    other => Err(Error::InvalidContainer(format!(
+      "unknown hash algorithm tag: {}",
+      other
+    ))),
+  }
+}
+
+// `Zstd`'s `level` doesn't fit a plain tag byte, so compress algorithms are
+// written/read as a tag followed by whatever payload that tag needs (none
+// for every other variant) - the same length-implicit-by-tag approach
+// `write_operation_record`/`read_operation_record` use for `Operation`.
+fn write_compress_algorithm<W: Write>(
+  writer: &mut W,
+  algorithm: CompressAlgorithm,
+) -> Result<(), Error> {
+  match algorithm {
+    CompressAlgorithm::None => write_u8(writer, 0),
+    CompressAlgorithm::Zstd { level } => {
+      write_u8(writer, 1)?;
+      write_u32(writer, level as u32)
+    }
+    CompressAlgorithm::Lz4 => write_u8(writer, 2),
+    CompressAlgorithm::Brotli => write_u8(writer, 3),
+    CompressAlgorithm::Bzip2 { level } => {
+      write_u8(writer, 4)?;
+      write_u32(writer, level)
+    }
+    CompressAlgorithm::Gzip { level } => {
+      write_u8(writer, 5)?;
+      write_u32(writer, level)
+    }
+    CompressAlgorithm::Deflate { level } => {
+      write_u8(writer, 6)?;
+      write_u32(writer, level)
+    }
+    CompressAlgorithm::Fsst => write_u8(writer, 7),
+  }
+}
+
+fn read_compress_algorithm<R: Read>(reader: &mut R) -> Result<CompressAlgorithm, Error> {
+  match read_u8(reader)? {
+    0 => Ok(CompressAlgorithm::None),
+    1 => Ok(CompressAlgorithm::Zstd {
+      level: read_u32(reader)? as i32,
+    }),
+    2 => Ok(CompressAlgorithm::Lz4),
+    3 => Ok(CompressAlgorithm::Brotli),
+    4 => Ok(CompressAlgorithm::Bzip2 {
+      level: read_u32(reader)?,
+    }),
+    5 => Ok(CompressAlgorithm::Gzip {
+      level: read_u32(reader)?,
+    }),
+    6 => Ok(CompressAlgorithm::Deflate {
+      level: read_u32(reader)?,
+    }),
+    7 => Ok(CompressAlgorithm::Fsst),
+    other => Err(Error::InvalidContainer(format!(
+      "unknown compress algorithm tag: {}",
+      other
+    ))),
+  }
+}
+
+fn diff_algorithm_tag(algorithm: DiffAlgorithm) -> u8 {
+  match algorithm {
+    DiffAlgorithm::Rsync020 => 0,
+    DiffAlgorithm::Bidiff1 => 1,
+    DiffAlgorithm::Cdc => 2,
+    DiffAlgorithm::FastCdc1 => 3,
+    DiffAlgorithm::Cdc1 => 4,
+  }
+}
+
+fn diff_algorithm_from_tag(tag: u8) -> Result<DiffAlgorithm, Error> {
+  match tag {
+    0 => Ok(DiffAlgorithm::Rsync020),
+    1 => Ok(DiffAlgorithm::Bidiff1),
+    2 => Ok(DiffAlgorithm::Cdc),
+    3 => Ok(DiffAlgorithm::FastCdc1),
+    4 => Ok(DiffAlgorithm::Cdc1),
+    other => Err(Error::InvalidContainer(format!(
+      "unknown diff algorithm tag: {}",
+      other
+    ))),
+  }
+}
+
+fn write_fsst_table<W: Write>(
+  writer: &mut W,
+  table: &Option<crate::fsst::SymbolTable>,
+) -> Result<(), Error> {
+  match table {
+    Some(table) => {
+      write_u8(writer, 1)?;
+      write_u32(writer, table.symbols().len() as u32)?;
+      for symbol in table.symbols() {
+        write_bytes(writer, symbol)?;
+      }
+    }
+    None => write_u8(writer, 0)?,
+  }
+  Ok(())
+}
+
+fn read_fsst_table<R: Read>(
+  reader: &mut R,
+) -> Result<Option<crate::fsst::SymbolTable>, Error> {
+  match read_u8(reader)? {
+    0 => Ok(None),
+    _ => {
+      let count = read_u32(reader)?;
+      let mut symbols = Vec::with_capacity(count as usize);
+      for _ in 0..count {
+        symbols.push(read_bytes(reader)?);
+      }
+      Ok(Some(crate::fsst::SymbolTable::from_symbols(symbols)))
+    }
+  }
+}
+
+fn write_file_metadata<W: Write>(
+  writer: &mut W,
+  metadata: &FileMetadata,
+) -> Result<(), Error> {
+  write_u32(writer, metadata.last_modified.0 as u32)?;
+  write_u32(writer, metadata.last_modified.1 as u32)?;
+  match metadata.unix_mode {
+    Some(mode) => {
+      write_u8(writer, 1)?;
+      write_u32(writer, mode)?;
+    }
+    None => write_u8(writer, 0)?,
+  }
+  write_u32(writer, metadata.compression_method as u32)?;
+  write_u8(writer, metadata.encrypted as u8)?;
+  write_bytes(writer, &metadata.extra_field)?;
+  Ok(())
+}
+
+fn read_file_metadata<R: Read>(reader: &mut R) -> Result<FileMetadata, Error> {
+  let last_modified = (read_u32(reader)? as u16, read_u32(reader)? as u16);
+  let unix_mode = match read_u8(reader)? {
+    0 => None,
+    _ => Some(read_u32(reader)?),
+  };
+  let compression_method = read_u32(reader)? as u16;
+  let encrypted = read_u8(reader)? != 0;
+  let extra_field = read_bytes(reader)?;
+  Ok(FileMetadata {
+    last_modified,
+    unix_mode,
+    compression_method,
+    encrypted,
+    extra_field,
+  })
+}
+
+fn write_patch<W: Write>(writer: &mut W, patch: &Patch) -> Result<(), Error> {
+  write_u8(writer, diff_algorithm_tag(patch.diff_algorithm))?;
+  write_compress_algorithm(writer, patch.compress_algorithm)?;
+  write_u8(writer, hash_algorithm_tag(patch.hash_algorithm))?;
+  write_string(writer, &patch.before_hash)?;
+  write_string(writer, &patch.after_hash)?;
+  write_bytes(writer, &patch.patch)?;
+  match patch.block_size {
+    Some(block_size) => {
+      write_u8(writer, 1)?;
+      write_u32(writer, block_size)?;
+    }
+    None => write_u8(writer, 0)?,
+  }
+  write_u8(writer, patch.encrypted as u8)?;
+  Ok(())
+}
+
+fn read_patch<R: Read>(reader: &mut R) -> Result<Patch, Error> {
+  let diff_algorithm = diff_algorithm_from_tag(read_u8(reader)?)?;
+  let compress_algorithm = read_compress_algorithm(reader)?;
+  let hash_algorithm = hash_algorithm_from_tag(read_u8(reader)?)?;
+  let before_hash = read_string(reader)?;
+  let after_hash = read_string(reader)?;
+  let patch = read_bytes(reader)?;
+  let block_size = match read_u8(reader)? {
+    0 => None,
+    _ => Some(read_u32(reader)?),
+  };
+  let encrypted = read_u8(reader)? != 0;
+  Ok(Patch {
+    diff_algorithm,
+    compress_algorithm,
+    hash_algorithm,
+    before_hash,
+    after_hash,
+    patch,
+    block_size,
+    encrypted,
+  })
+}
+
+// Operation records are written into a buffer first and then emitted as one
+// length-prefixed blob, so a reader always consumes exactly one whole record
+// at a time. A record whose tag this build doesn't recognize is rejected
+// with `Error::InvalidContainer` rather than skipped.
+fn write_operation_record<W: Write>(
+  writer: &mut W,
+  path: &str,
+  operation: &Operation,
+) -> Result<(), Error> {
+  let mut record = Vec::new();
+  write_string(&mut record, path)?;
+
+  match operation {
+    Operation::Patch(patch) => {
+      write_u8(&mut record, TAG_PATCH)?;
+      write_patch(&mut record, patch)?;
+    }
+    Operation::PutFile {
+      compress_algorithm,
+      data,
+    } => {
+      write_u8(&mut record, TAG_PUT_FILE)?;
+      write_compress_algorithm(&mut record, *compress_algorithm)?;
+      write_bytes(&mut record, data)?;
+    }
+    Operation::DeleteFile => write_u8(&mut record, TAG_DELETE_FILE)?,
+    Operation::FileStaysSame => write_u8(&mut record, TAG_FILE_STAYS_SAME)?,
+    Operation::MetadataOnly(metadata) => {
+      write_u8(&mut record, TAG_METADATA_ONLY)?;
+      write_file_metadata(&mut record, metadata)?;
+    }
+    Operation::CopyFrom(source) => {
+      write_u8(&mut record, TAG_COPY_FROM)?;
+      write_string(&mut record, source)?;
+    }
+    Operation::Chunked(hashes) => {
+      write_u8(&mut record, TAG_CHUNKED)?;
+      write_u32(&mut record, hashes.len() as u32)?;
+      for content_hash in hashes {
+        write_string(&mut record, content_hash)?;
+      }
+    }
+    Operation::MoveFile { from, patch } => {
+      write_u8(&mut record, TAG_MOVE_FILE)?;
+      write_string(&mut record, from)?;
+      match patch {
+        Some(patch) => {
+          write_u8(&mut record, 1)?;
+          write_patch(&mut record, patch)?;
+        }
+        None => write_u8(&mut record, 0)?,
+      }
+    }
+    Operation::DeltaFrom { source, patch } => {
+      write_u8(&mut record, TAG_DELTA_FROM)?;
+      write_string(&mut record, source)?;
+      write_patch(&mut record, patch)?;
+    }
+  }
+
+  write_bytes(writer, &record)
+}
+
+fn read_operation_record<R: Read>(
+  reader: &mut R,
+) -> Result<(Filename, Operation), Error> {
+  let record = read_bytes(reader)?;
+  let cursor = &mut &record[..];
+
+  let path = read_string(cursor)?;
+  let tag = read_u8(cursor)?;
+  let operation = match tag {
+    TAG_PATCH => Operation::Patch(read_patch(cursor)?),
+    TAG_PUT_FILE => {
+      let compress_algorithm = read_compress_algorithm(cursor)?;
+      let data = read_bytes(cursor)?;
+      Operation::PutFile {
+        compress_algorithm,
+        data,
+      }
+    }
+    TAG_DELETE_FILE => Operation::DeleteFile,
+    TAG_FILE_STAYS_SAME => Operation::FileStaysSame,
+    TAG_METADATA_ONLY => Operation::MetadataOnly(read_file_metadata(cursor)?),
+    TAG_COPY_FROM => Operation::CopyFrom(read_string(cursor)?),
+    TAG_CHUNKED => {
+      let count = read_u32(cursor)?;
+      let mut hashes = Vec::with_capacity(count as usize);
+      for _ in 0..count {
+        hashes.push(read_string(cursor)?);
+      }
+      Operation::Chunked(hashes)
+    }
+    TAG_MOVE_FILE => {
+      let from = read_string(cursor)?;
+      let patch = match read_u8(cursor)? {
+        0 => None,
+        _ => Some(read_patch(cursor)?),
+      };
+      Operation::MoveFile { from, patch }
+    }
+    TAG_DELTA_FROM => {
+      let source = read_string(cursor)?;
+      let patch = read_patch(cursor)?;
+      Operation::DeltaFrom { source, patch }
+    }
+    other => {
+      return Err(Error::InvalidContainer(format!(
+        "unknown operation tag: {}",
+        other
+      )));
+    }
+  };
+
+  Ok((path, operation))
+}
+
+/// Writes `patch_set` to `writer` in the versioned container format
+/// described in the module docs.
+pub(crate) fn write_to<W: Write>(
+  patch_set: &PatchSet,
+  writer: &mut W,
+) -> Result<(), Error> {
+  writer
+    .write_all(MAGIC)
+    .map_err(|e| Error::IoError(e.to_string()))?;
+  write_u8(writer, FORMAT_VERSION)?;
+  write_u8(writer, hash_algorithm_tag(patch_set.hash_algorithm))?;
+  write_string(writer, &patch_set.hash_before)?;
+  write_string(writer, &patch_set.operations_hash)?;
+
+  write_u32(writer, patch_set.metadata.len() as u32)?;
+  for (path, metadata) in &patch_set.metadata {
+    write_string(writer, path)?;
+    write_file_metadata(writer, metadata)?;
+  }
+
+  write_u32(writer, patch_set.chunk_store.len() as u32)?;
+  for (content_hash, data) in &patch_set.chunk_store {
+    write_string(writer, content_hash)?;
+    write_bytes(writer, data)?;
+  }
+
+  write_fsst_table(writer, &patch_set.fsst_table)?;
+
+  write_u32(writer, patch_set.operations.0.len() as u32)?;
+  for (path, operation) in &patch_set.operations.0 {
+    write_operation_record(writer, path, operation)?;
+  }
+
+  Ok(())
+}
+
+/// Reads the header (everything up to, but not including, the operation
+/// records) from `reader`. Used directly by
+/// [`crate::zip::apply_zip_streaming`] so it can verify `hash_before` and
+/// then read operations one at a time via [`read_next_operation`] instead of
+/// collecting them all up front.
+pub(crate) fn read_header<R: Read>(reader: &mut R) -> Result<ContainerHeader, Error> {
+  let mut magic = [0u8; 4];
+  reader
+    .read_exact(&mut magic)
+    .map_err(|e| Error::InvalidContainer(e.to_string()))?;
+  if &magic != MAGIC {
+    return Err(Error::InvalidContainer(
+      "bad magic number, not a files_diff patch container".to_string(),
+    ));
+  }
+
+  let version = read_u8(reader)?;
+  if version != FORMAT_VERSION {
+    return Err(Error::UnsupportedContainerVersion(version));
+  }
+
+  let hash_algorithm = hash_algorithm_from_tag(read_u8(reader)?)?;
+  let hash_before = read_string(reader)?;
+  let operations_hash = read_string(reader)?;
+
+  let metadata_count = read_u32(reader)?;
+  let mut metadata = std::collections::HashMap::with_capacity(metadata_count as usize);
+  for _ in 0..metadata_count {
+    let path = read_string(reader)?;
+    let file_metadata = read_file_metadata(reader)?;
+    metadata.insert(path, file_metadata);
+  }
+
+  let chunk_store_count = read_u32(reader)?;
+  let mut chunk_store =
+    std::collections::HashMap::with_capacity(chunk_store_count as usize);
+  for _ in 0..chunk_store_count {
+    let content_hash = read_string(reader)?;
+    let data = read_bytes(reader)?;
+    chunk_store.insert(content_hash, data);
+  }
+
+  let fsst_table = read_fsst_table(reader)?;
+
+  let operation_count = read_u32(reader)?;
+
+  Ok(ContainerHeader {
+    hash_algorithm,
+    hash_before,
+    operations_hash,
+    metadata,
+    chunk_store,
+    fsst_table,
+    operation_count,
+  })
+}
+
+/// Reads the next operation record from `reader`. Must be called exactly
+/// `header.operation_count` times after [`read_header`], in the order the
+/// operations were written.
+pub(crate) fn read_next_operation<R: Read>(
+  reader: &mut R,
+) -> Result<(Filename, Operation), Error> {
+  read_operation_record(reader)
+}
+
+/// Reads a full [`PatchSet`] out of `reader`, written by [`write_to`].
+/// Unlike [`crate::zip::apply_zip_streaming`], this collects every operation
+/// into memory, so prefer it only when the caller actually needs a
+/// `PatchSet` value (for inspection, re-serializing with `to_bytes`, etc.)
+/// rather than applying it directly.
+pub(crate) fn read_from<R: Read>(reader: &mut R) -> Result<PatchSet, Error> {
+  let header = read_header(reader)?;
+  let mut operations = Vec::with_capacity(header.operation_count as usize);
+  for _ in 0..header.operation_count {
+    operations.push(read_next_operation(reader)?);
+  }
+
+  Ok(PatchSet {
+    operations: crate::patch::Operations(operations),
+    hash_algorithm: header.hash_algorithm,
+    hash_before: header.hash_before,
+    operations_hash: header.operations_hash,
+    metadata: header.metadata,
+    chunk_store: header.chunk_store,
+    fsst_table: header.fsst_table,
+  })
+}
@@ -0,0 +1,251 @@
+//! Content-defined chunking and cross-source dedup, backing
+//! `DiffAlgorithm::Cdc1`.
+//!
+//! Like `crate::fast_cdc` (`DiffAlgorithm::FastCdc1`), this produces a single
+//! whole-file `Patch` built from Gear-hash chunks rather than a shared chunk
+//! store. It differs in two ways: the cut point uses a single fixed mask
+//! instead of `FastCdc1`'s size-normalized two-mask scheme, and a chunk is
+//! interned against both `before`'s chunks *and* every chunk already emitted
+//! earlier in `after` - so a region repeated within `after` itself (e.g. a
+//! duplicated block, or content shifted rather than edited) is also
+//! deduplicated, not just one reused verbatim from `before`.
+
+use super::*;
+
+const AVG_CHUNK_SIZE: usize = 8 * 1024;
+const MIN_CHUNK_SIZE: usize = 2 * 1024;
+const MAX_CHUNK_SIZE: usize = 64 * 1024;
+
+// Unlike `crate::fast_cdc`'s normalized two-mask scheme, a single fixed mask
+// sized to the target average chunk length - simpler, at the cost of a wider
+// spread around `AVG_CHUNK_SIZE`.
+const CUT_MASK: u64 = (AVG_CHUNK_SIZE - 1) as u64;
+
+pub(super) struct DedupDiffMachine;
+
+static GEAR_TABLE: [u64; 256] = generate_gear_table();
+
+// Deterministic splitmix64-derived table, distinct from `crate::fast_cdc`'s
+// (different seed), computed at compile time so chunk boundaries - and
+// therefore patches - are stable across builds and machines.
+const fn generate_gear_table() -> [u64; 256] {
+  let mut table = [0u64; 256];
+  let mut seed: u64 = 0xA5A5A5A5DEADBEEF;
+  let mut i = 0;
+  while i < 256 {
+    seed = seed.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = seed;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^= z >> 31;
+    table[i] = z;
+    i += 1;
+  }
+  table
+}
+
+struct Chunk {
+  range: std::ops::Range<usize>,
+}
+
+// Splits `data` into content-defined chunks with a Gear-hash rolling
+// checksum: `h = (h << 1) + Gear[byte]`, cutting when `h & CUT_MASK == 0`.
+fn chunk(data: &[u8]) -> Vec<Chunk> {
+  if data.is_empty() {
+    return Vec::new();
+  }
+
+  let mut chunks = Vec::new();
+  let mut start = 0;
+  let mut h: u64 = 0;
+
+  for pos in 0..data.len() {
+    h = h.wrapping_shl(1).wrapping_add(GEAR_TABLE[data[pos] as usize]);
+
+    let len = pos + 1 - start;
+    let at_hash_boundary = len >= MIN_CHUNK_SIZE && (h & CUT_MASK == 0);
+    let at_end = pos + 1 == data.len();
+
+    if at_hash_boundary || len >= MAX_CHUNK_SIZE || at_end {
+      chunks.push(Chunk {
+        range: start..pos + 1,
+      });
+      start = pos + 1;
+      h = 0;
+    }
+  }
+
+  chunks
+}
+
+// Tag bytes for the entries making up the operation sequence (before it's
+// hash-guarded and compressed): each `after` chunk is either a reference to
+// a chunk already seen - in `before` or earlier in `after` - or the literal
+// bytes when no match exists yet.
+const TAG_REUSE: u8 = 0;
+const TAG_LITERAL: u8 = 1;
+
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+  out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_bytes(out: &mut Vec<u8>, value: &[u8]) {
+  write_u32(out, value.len() as u32);
+  out.extend_from_slice(value);
+}
+
+fn read_u32(input: &[u8], pos: &mut usize) -> Result<u32, Error> {
+  let bytes = input.get(*pos..*pos + 4).ok_or_else(|| {
+    Error::DedupError("truncated dedup patch".to_string())
+  })?;
+  *pos += 4;
+  Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_bytes<'a>(input: &'a [u8], pos: &mut usize) -> Result<&'a [u8], Error> {
+  let len = read_u32(input, pos)? as usize;
+  let bytes = input
+    .get(*pos..*pos + len)
+    .ok_or_else(|| Error::DedupError("truncated dedup patch".to_string()))?;
+  *pos += len;
+  Ok(bytes)
+}
+
+// Prefixes the encoded operation sequence with a hash of itself, so `apply`
+// can detect a corrupted/truncated operation stream (`Error::
+// OperationsHashMismatch`) before it ever tries to walk it and resolve
+// chunk references - the same guard `PatchSet::operations_hash` gives the
+// zip-wide operation list, scoped here to a single `Patch.patch` payload
+// instead of adding a field to the shared `Patch` struct.
+fn write_operations_hash(out: &mut Vec<u8>, operations: &[u8]) {
+  write_bytes(out, crate::hash(operations).as_bytes());
+}
+
+fn read_operations_hash<'a>(
+  input: &'a [u8],
+  pos: &mut usize,
+) -> Result<&'a [u8], Error> {
+  read_bytes(input, pos)
+}
+
+impl DiffMachine for DedupDiffMachine {
+  fn diff(
+    before: &[u8],
+    after: &[u8],
+    compress_algorithm: CompressAlgorithm,
+  ) -> Result<Patch, Error> {
+    // Seeded from `before`'s chunks, then grown with every `after` chunk as
+    // it's emitted, so later `after` chunks can intern against earlier ones
+    // from either source instead of only ever matching `before`.
+    let mut known_chunks: std::collections::HashMap<String, std::ops::Range<usize>> =
+      chunk(before)
+        .into_iter()
+        .map(|c| (crate::hash(&before[c.range.clone()]), c.range))
+        .collect();
+
+    let after_chunks = chunk(after);
+
+    let mut operations = Vec::new();
+    write_u32(&mut operations, after_chunks.len() as u32);
+    for c in after_chunks {
+      let data = &after[c.range.clone()];
+      let content_hash = crate::hash(data);
+      match known_chunks.get(&content_hash) {
+        Some(_) => {
+          operations.push(TAG_REUSE);
+          write_bytes(&mut operations, content_hash.as_bytes());
+        }
+        None => {
+          operations.push(TAG_LITERAL);
+          write_bytes(&mut operations, data);
+          known_chunks.insert(content_hash, c.range);
+        }
+      }
+    }
+
+    let mut encoded = Vec::new();
+    write_operations_hash(&mut encoded, &operations);
+    encoded.extend_from_slice(&operations);
+
+    let compressed_patch = compress_algorithm.compress(&encoded)?;
+
+    Ok(Patch {
+      diff_algorithm: DiffAlgorithm::Cdc1,
+      compress_algorithm,
+      hash_algorithm: crate::hash::DEFAULT_HASH_ALGORITHM,
+      before_hash: crate::hash::hash_with(before, crate::hash::DEFAULT_HASH_ALGORITHM),
+      after_hash: crate::hash::hash_with(after, crate::hash::DEFAULT_HASH_ALGORITHM),
+      patch: compressed_patch,
+      block_size: None,
+      encrypted: false,
+    })
+  }
+
+  fn apply(base: &[u8], delta: &Patch) -> Result<Vec<u8>, Error> {
+    assert!(delta.diff_algorithm == DiffAlgorithm::Cdc1);
+
+    let base_hash = crate::hash::hash_with(base, delta.hash_algorithm);
+    if base_hash != delta.before_hash {
+      return Err(Error::BeforeHashMismatch);
+    }
+
+    let encoded = delta.compress_algorithm.decompress(&delta.patch)?;
+
+    let mut pos = 0;
+    let operations_hash = read_operations_hash(&encoded, &mut pos)?;
+    let operations = &encoded[pos..];
+    if crate::hash(operations).as_bytes() != operations_hash {
+      return Err(Error::OperationsHashMismatch);
+    }
+
+    // Seeded from `base`'s chunks, then grown with every literal chunk as
+    // it's decoded, mirroring how `diff` grows `known_chunks` as it encodes -
+    // so a `TAG_REUSE` entry can resolve to a chunk that only ever existed
+    // in `after`, not just ones present in `base`.
+    let mut known_chunks: std::collections::HashMap<String, Vec<u8>> = chunk(base)
+      .into_iter()
+      .map(|c| (crate::hash(&base[c.range.clone()]), base[c.range].to_vec()))
+      .collect();
+
+    let mut op_pos = 0;
+    let chunk_count = read_u32(operations, &mut op_pos)?;
+
+    let mut after = Vec::new();
+    for _ in 0..chunk_count {
+      let tag = *operations
+        .get(op_pos)
+        .ok_or_else(|| Error::DedupError("truncated dedup patch".to_string()))?;
+      op_pos += 1;
+
+      match tag {
+        TAG_REUSE => {
+          let hash_bytes = read_bytes(operations, &mut op_pos)?;
+          let content_hash = std::str::from_utf8(hash_bytes)
+            .map_err(|e| Error::DedupError(e.to_string()))?;
+          let data = known_chunks.get(content_hash).ok_or_else(|| {
+            Error::MissingChunk(content_hash.to_string())
+          })?;
+          after.extend_from_slice(data);
+        }
+        TAG_LITERAL => {
+          let data = read_bytes(operations, &mut op_pos)?;
+          known_chunks.insert(crate::hash(data), data.to_vec());
+          after.extend_from_slice(data);
+        }
+        other => {
+          return Err(Error::DedupError(format!(
+            "unknown dedup patch entry tag: {}",
+            other
+          )));
+        }
+      }
+    }
+
+    let after_hash = crate::hash::hash_with(&after, delta.hash_algorithm);
+    if after_hash != delta.after_hash {
+      return Err(Error::AfterHashMismatch);
+    }
+
+    Ok(after)
+  }
+}
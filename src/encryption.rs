@@ -0,0 +1,197 @@
+//! Optional authenticated encryption for a serialized [`crate::patch::PatchSet`].
+//!
+//! [`PatchSet::to_bytes`](crate::patch::PatchSet::to_bytes) and
+//! [`PatchSet::write_to`](crate::patch::PatchSet::write_to) are fine when a
+//! patch never leaves a trusted machine; shipping one over an untrusted
+//! channel (object storage, a CDN, an update server) calls for
+//! confidentiality and tamper-evidence on top, which is what this module
+//! adds: a passphrase-derived key, an AEAD cipher, and a small header
+//! recording the salt, nonce, and algorithm used so the same passphrase
+//! reproduces the key on decrypt.
+//!
+//! # Format
+//!
+//! ```text
+//! magic      4 bytes   b"FDEP"
+//! version    1 byte    FORMAT_VERSION
+//! algorithm  1 byte    tag, see `encryption_tag`
+//! salt       16 bytes  present only when algorithm != None
+//! nonce      12 bytes  present only when algorithm != None
+//! payload    bytes     ciphertext (with appended AEAD tag), or plaintext
+//!                      verbatim when algorithm == None
+//! ```
+
+use aes_gcm::aead::{Aead, KeyInit, OsRng, rand_core::RngCore};
+use aes_gcm::{Aes256Gcm, Nonce as Aes256GcmNonce};
+use argon2::Argon2;
+use chacha20poly1305::{ChaCha20Poly1305, Nonce as ChaChaNonce};
+
+use crate::Error;
+
+const MAGIC: &[u8; 4] = b"FDEP";
+const FORMAT_VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = MAGIC.len() + 1 + 1;
+
+/// Authenticated encryption algorithm used to protect a serialized patch
+/// set in transit. Recorded alongside the ciphertext (see the module docs),
+/// so [`decrypt`] doesn't need to be told which one was used to [`encrypt`].
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Encryption {
+  /// No encryption. The patch set is still wrapped in this module's
+  /// header, so it round-trips through [`encrypt`]/[`decrypt`], but the
+  /// bytes are otherwise stored as-is.
+  None,
+  /// AES-256 in Galois/Counter Mode. The default choice: hardware-
+  /// accelerated on most modern CPUs and widely reviewed.
+  Aes256Gcm,
+  /// ChaCha20-Poly1305. Use this when the patch may be encrypted or
+  /// applied on hardware without AES instructions, where it's
+  /// meaningfully faster than AES-GCM.
+  ChaCha20Poly1305,
+}
+
+impl std::fmt::Display for Encryption {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{:?}", self)
+  }
+}
+
+fn encryption_tag(encryption: Encryption) -> u8 {
+  match encryption {
+    Encryption::None => 0,
+    Encryption::Aes256Gcm => 1,
+    Encryption::ChaCha20Poly1305 => 2,
+  }
+}
+
+fn encryption_from_tag(tag: u8) -> Result<Encryption, Error> {
+  match tag {
+    0 => Ok(Encryption::None),
+    1 => Ok(Encryption::Aes256Gcm),
+    2 => Ok(Encryption::ChaCha20Poly1305),
+    other => Err(Error::InvalidEncryptedPatch(format!(
+      "unknown encryption algorithm tag: {}",
+      other
+    ))),
+  }
+}
+
+// Derives a 256-bit key from `passphrase` and `salt` with Argon2id. The
+// salt is random per-encryption (see `encrypt`) and stored alongside the
+// ciphertext, so the same passphrase always re-derives the same key given
+// the salt from the header, without the passphrase itself ever being
+// stored anywhere.
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<[u8; 32], Error> {
+  let mut key = [0u8; 32];
+  Argon2::default()
+    .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+    .map_err(|e| Error::EncryptionFailed(e.to_string()))?;
+  Ok(key)
+}
+
+/// Encrypts `plaintext` (typically `patch_set.to_bytes()?`) with a key
+/// derived from `passphrase`, returning the header-prefixed ciphertext.
+/// `encryption = Encryption::None` still applies the header (for a uniform
+/// [`decrypt`] entry point) but performs no actual encryption.
+pub(crate) fn encrypt(
+  plaintext: &[u8],
+  passphrase: &str,
+  encryption: Encryption,
+) -> Result<Vec<u8>, Error> {
+  let mut out = Vec::with_capacity(HEADER_LEN + plaintext.len());
+  out.extend_from_slice(MAGIC);
+  out.push(FORMAT_VERSION);
+  out.push(encryption_tag(encryption));
+
+  if encryption == Encryption::None {
+    out.extend_from_slice(plaintext);
+    return Ok(out);
+  }
+
+  let mut salt = [0u8; SALT_LEN];
+  OsRng.fill_bytes(&mut salt);
+  let mut nonce_bytes = [0u8; NONCE_LEN];
+  OsRng.fill_bytes(&mut nonce_bytes);
+  let key = derive_key(passphrase, &salt)?;
+
+  let ciphertext = match encryption {
+    Encryption::Aes256Gcm => {
+      let cipher = Aes256Gcm::new_from_slice(&key)
+        .map_err(|e| Error::EncryptionFailed(e.to_string()))?;
+      cipher
+        .encrypt(Aes256GcmNonce::from_slice(&nonce_bytes), plaintext)
+        .map_err(|e| Error::EncryptionFailed(e.to_string()))?
+    }
+    Encryption::ChaCha20Poly1305 => {
+      let cipher = ChaCha20Poly1305::new_from_slice(&key)
+        .map_err(|e| Error::EncryptionFailed(e.to_string()))?;
+      cipher
+        .encrypt(ChaChaNonce::from_slice(&nonce_bytes), plaintext)
+        .map_err(|e| Error::EncryptionFailed(e.to_string()))?
+    }
+    Encryption::None => unreachable!("handled above"),
+  };
+
+  out.extend_from_slice(&salt);
+  out.extend_from_slice(&nonce_bytes);
+  out.extend_from_slice(&ciphertext);
+  Ok(out)
+}
+
+/// Reverses [`encrypt`]: derives the same key from `passphrase` using the
+/// salt recorded in the header, verifies the AEAD tag, and returns the
+/// recovered plaintext. Returns [`Error::AuthenticationFailed`] if the tag
+/// doesn't verify - either `passphrase` is wrong, or `bytes` was tampered
+/// with or corrupted.
+pub(crate) fn decrypt(bytes: &[u8], passphrase: &str) -> Result<Vec<u8>, Error> {
+  if bytes.len() < HEADER_LEN || &bytes[..MAGIC.len()] != MAGIC {
+    return Err(Error::InvalidEncryptedPatch(
+      "bad magic number, not an encrypted files_diff patch".to_string(),
+    ));
+  }
+
+  let version = bytes[MAGIC.len()];
+  if version != FORMAT_VERSION {
+    return Err(Error::InvalidEncryptedPatch(format!(
+      "unsupported format version: {}",
+      version
+    )));
+  }
+
+  let encryption = encryption_from_tag(bytes[MAGIC.len() + 1])?;
+  let rest = &bytes[HEADER_LEN..];
+
+  if encryption == Encryption::None {
+    return Ok(rest.to_vec());
+  }
+
+  if rest.len() < SALT_LEN + NONCE_LEN {
+    return Err(Error::InvalidEncryptedPatch(
+      "truncated salt/nonce header".to_string(),
+    ));
+  }
+  let salt = &rest[..SALT_LEN];
+  let nonce_bytes = &rest[SALT_LEN..SALT_LEN + NONCE_LEN];
+  let ciphertext = &rest[SALT_LEN + NONCE_LEN..];
+  let key = derive_key(passphrase, salt)?;
+
+  match encryption {
+    Encryption::Aes256Gcm => {
+      let cipher = Aes256Gcm::new_from_slice(&key)
+        .map_err(|e| Error::EncryptionFailed(e.to_string()))?;
+      cipher
+        .decrypt(Aes256GcmNonce::from_slice(nonce_bytes), ciphertext)
+        .map_err(|_| Error::AuthenticationFailed)
+    }
+    Encryption::ChaCha20Poly1305 => {
+      let cipher = ChaCha20Poly1305::new_from_slice(&key)
+        .map_err(|e| Error::EncryptionFailed(e.to_string()))?;
+      cipher
+        .decrypt(ChaChaNonce::from_slice(nonce_bytes), ciphertext)
+        .map_err(|_| Error::AuthenticationFailed)
+    }
+    Encryption::None => unreachable!("handled above"),
+  }
+}
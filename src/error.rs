@@ -11,7 +11,7 @@
 /// let before = b"original";
 /// let after = b"modified";
 ///
-/// match diff(before, after, DiffAlgorithm::Rsync020, CompressAlgorithm::Zstd) {
+/// match diff(before, after, DiffAlgorithm::Rsync020, CompressAlgorithm::Zstd { level: 21 }) {
 ///     Ok(patch) => println!("Patch generated successfully"),
 ///     Err(e) => match e {
 ///         files_diff::Error::IoError(msg) => eprintln!("IO error: {}", msg),
@@ -28,9 +28,27 @@ pub enum Error {
   /// An error occurred while applying an rsync patch
   RsyncApplyError(fast_rsync::ApplyError),
 
+  /// A malformed or truncated rsync delta command stream was encountered by
+  /// `crate::rsync::apply_with`'s `ApplyMode::LessMemory` path, which walks
+  /// the command stream itself instead of delegating to `fast_rsync::apply`
+  RsyncStreamError(String),
+
   /// An error occurred in the bidiff algorithm
   BidiffError(String),
 
+  /// An error occurred in the FastCDC diff algorithm (a malformed or
+  /// truncated encoded chunk sequence)
+  FastCdcError(String),
+
+  /// A malformed or truncated FSST-encoded (`CompressAlgorithm::Fsst`)
+  /// payload, or an attempt to decode one without its `PatchSet`'s trained
+  /// symbol table
+  FsstError(String),
+
+  /// An error occurred in the content-defined chunking + dedup diff
+  /// algorithm (a malformed or truncated encoded chunk sequence)
+  DedupError(String),
+
   /// The hash of the source file doesn't match the expected hash
   BeforeHashMismatch,
 
@@ -46,9 +64,53 @@ pub enum Error {
   /// An error occurred while processing a zip archive
   ZipError(String),
 
+  /// An error occurred while processing a tar archive
+  TarError(String),
+
+  /// Failed to decrypt an AES-encrypted zip entry, because no password (or
+  /// the wrong password) was supplied
+  DecryptionFailed(String),
+
+  /// A chunk hash referenced by an `Operation::Chunked` or by a chunk-based
+  /// `Patch` isn't present in the chunks it's resolved against (the patch
+  /// set's chunk store, or the chunks known from the base)
+  MissingChunk(String),
+
   /// An error occurred while serializing a patch or patch set
   SerializeError(rkyv::rancor::Error),
 
   /// An error occurred while deserializing a patch or patch set
   DeserializeError(rkyv::rancor::Error),
+
+  /// The binary container read by [`crate::container`] was truncated or
+  /// otherwise malformed (bad magic number, unknown tag byte, a
+  /// length-prefixed record that ran past the available bytes, ...)
+  InvalidContainer(String),
+
+  /// The binary container's format version is newer than this build of the
+  /// crate knows how to read
+  UnsupportedContainerVersion(u8),
+
+  /// An encrypted patch blob produced by [`crate::patch::PatchSet::encrypt`]
+  /// was truncated or otherwise malformed (bad magic number, unsupported
+  /// version, a header shorter than its salt/nonce)
+  InvalidEncryptedPatch(String),
+
+  /// Encrypting or deriving a key for a patch failed
+  EncryptionFailed(String),
+
+  /// Decrypting an encrypted patch failed because the AEAD tag didn't
+  /// verify - either the passphrase was wrong, or the bytes were tampered
+  /// with or corrupted in transit
+  AuthenticationFailed,
+
+  /// [`crate::patch::Patch::encrypt_payload`] failed to encrypt the
+  /// patch's `patch` bytes
+  EncryptError(String),
+
+  /// [`crate::patch::Patch::decrypt_payload`] failed to authenticate or
+  /// decrypt the patch's `patch` bytes - either the passphrase was wrong,
+  /// `patch.patch` wasn't actually encrypted, or the bytes were tampered
+  /// with or corrupted in transit
+  DecryptError(String),
 }
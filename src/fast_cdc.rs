@@ -0,0 +1,220 @@
+//! FastCDC content-defined chunking, backing `DiffAlgorithm::FastCdc1`.
+//!
+//! Unlike `crate::cdc` (used by `DiffAlgorithm::Cdc` for zip-wide chunk
+//! deduplication via `Operation::Chunked`), this produces a single
+//! whole-file `Patch`: both `before` and `after` are split into chunks, and
+//! the patch records, per `after` chunk, either a reference to a matching
+//! chunk already present in `before` or the literal bytes when no match
+//! exists. That gives much smaller patches than `Bidiff1` and is much
+//! faster than `Rsync020` on large, mostly-similar binary files, without
+//! needing to exchange or store an rsync-style signature.
+
+use super::*;
+
+const AVG_CHUNK_SIZE: usize = 8 * 1024;
+const MIN_CHUNK_SIZE: usize = 2 * 1024;
+const MAX_CHUNK_SIZE: usize = 64 * 1024;
+
+// Normalized chunking (FastCDC's "normalization level 2"): a stricter mask
+// (more 1-bits, so a match is rarer) is used below the target average,
+// discouraging very short chunks, and a looser mask (fewer 1-bits, so a
+// match is more likely) is used past it, pulling the cut back toward the
+// average instead of letting the chunk run all the way to `MAX_CHUNK_SIZE`.
+// Together these make chunk sizes cluster around `AVG_CHUNK_SIZE` instead of
+// spreading uniformly between the min and max.
+const MASK_SMALL: u64 = (1u64 << 15) - 1;
+const MASK_LARGE: u64 = (1u64 << 11) - 1;
+
+pub(super) struct FastCdcDiffMachine;
+
+static GEAR_TABLE: [u64; 256] = generate_gear_table();
+
+// Deterministic splitmix64-derived table, computed at compile time so chunk
+// boundaries (and therefore patches) are stable across builds and machines.
+const fn generate_gear_table() -> [u64; 256] {
+  let mut table = [0u64; 256];
+  let mut seed: u64 = 0x2545F4914F6CDD1D;
+  let mut i = 0;
+  while i < 256 {
+    seed = seed.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = seed;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^= z >> 31;
+    table[i] = z;
+    i += 1;
+  }
+  table
+}
+
+struct Chunk {
+  range: std::ops::Range<usize>,
+}
+
+// Splits `data` into content-defined chunks with a Gear-hash rolling
+// checksum: `h = (h << 1) + Gear[byte]`, cutting when `h & mask == 0`.
+fn chunk(data: &[u8]) -> Vec<Chunk> {
+  if data.is_empty() {
+    return Vec::new();
+  }
+
+  let mut chunks = Vec::new();
+  let mut start = 0;
+  let mut h: u64 = 0;
+
+  for pos in 0..data.len() {
+    h = h.wrapping_shl(1).wrapping_add(GEAR_TABLE[data[pos] as usize]);
+
+    let len = pos + 1 - start;
+    let mask = if len < AVG_CHUNK_SIZE {
+      MASK_SMALL
+    } else {
+      MASK_LARGE
+    };
+    let at_hash_boundary = len >= MIN_CHUNK_SIZE && (h & mask == 0);
+    let at_end = pos + 1 == data.len();
+
+    if at_hash_boundary || len >= MAX_CHUNK_SIZE || at_end {
+      chunks.push(Chunk {
+        range: start..pos + 1,
+      });
+      start = pos + 1;
+      h = 0;
+    }
+  }
+
+  chunks
+}
+
+// Tag bytes for the entries making up `Patch.patch`'s payload (before
+// compression): each `after` chunk is either a reference to a chunk already
+// present in `before`, or the literal bytes when no match exists.
+const TAG_REUSE: u8 = 0;
+const TAG_LITERAL: u8 = 1;
+
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+  out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_bytes(out: &mut Vec<u8>, value: &[u8]) {
+  write_u32(out, value.len() as u32);
+  out.extend_from_slice(value);
+}
+
+fn read_u32(input: &[u8], pos: &mut usize) -> Result<u32, Error> {
+  let bytes = input.get(*pos..*pos + 4).ok_or_else(|| {
+    Error::FastCdcError("truncated fast-cdc patch".to_string())
+  })?;
+  *pos += 4;
+  Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_bytes<'a>(input: &'a [u8], pos: &mut usize) -> Result<&'a [u8], Error> {
+  let len = read_u32(input, pos)? as usize;
+  let bytes = input
+    .get(*pos..*pos + len)
+    .ok_or_else(|| Error::FastCdcError("truncated fast-cdc patch".to_string()))?;
+  *pos += len;
+  Ok(bytes)
+}
+
+impl DiffMachine for FastCdcDiffMachine {
+  fn diff(
+    before: &[u8],
+    after: &[u8],
+    compress_algorithm: CompressAlgorithm,
+  ) -> Result<Patch, Error> {
+    let before_chunks: std::collections::HashMap<String, std::ops::Range<usize>> =
+      chunk(before)
+        .into_iter()
+        .map(|c| (crate::hash(&before[c.range.clone()]), c.range))
+        .collect();
+
+    let after_chunks = chunk(after);
+
+    let mut encoded = Vec::new();
+    write_u32(&mut encoded, after_chunks.len() as u32);
+    for c in after_chunks {
+      let data = &after[c.range];
+      let content_hash = crate::hash(data);
+      match before_chunks.get(&content_hash) {
+        Some(_) => {
+          encoded.push(TAG_REUSE);
+          write_bytes(&mut encoded, content_hash.as_bytes());
+        }
+        None => {
+          encoded.push(TAG_LITERAL);
+          write_bytes(&mut encoded, data);
+        }
+      }
+    }
+
+    let compressed_patch = compress_algorithm.compress(&encoded)?;
+
+    Ok(Patch {
+      diff_algorithm: DiffAlgorithm::FastCdc1,
+      compress_algorithm,
+      hash_algorithm: crate::hash::DEFAULT_HASH_ALGORITHM,
+      before_hash: crate::hash::hash_with(before, crate::hash::DEFAULT_HASH_ALGORITHM),
+      after_hash: crate::hash::hash_with(after, crate::hash::DEFAULT_HASH_ALGORITHM),
+      patch: compressed_patch,
+      block_size: None,
+      encrypted: false,
+    })
+  }
+
+  fn apply(base: &[u8], delta: &Patch) -> Result<Vec<u8>, Error> {
+    assert!(delta.diff_algorithm == DiffAlgorithm::FastCdc1);
+
+    let base_hash = crate::hash::hash_with(base, delta.hash_algorithm);
+    if base_hash != delta.before_hash {
+      return Err(Error::BeforeHashMismatch);
+    }
+
+    let base_chunks: std::collections::HashMap<String, &[u8]> = chunk(base)
+      .into_iter()
+      .map(|c| (crate::hash(&base[c.range.clone()]), &base[c.range]))
+      .collect();
+
+    let encoded = delta.compress_algorithm.decompress(&delta.patch)?;
+
+    let mut pos = 0;
+    let chunk_count = read_u32(&encoded, &mut pos)?;
+
+    let mut after = Vec::new();
+    for _ in 0..chunk_count {
+      let tag = *encoded
+        .get(pos)
+        .ok_or_else(|| Error::FastCdcError("truncated fast-cdc patch".to_string()))?;
+      pos += 1;
+
+      match tag {
+        TAG_REUSE => {
+          let hash_bytes = read_bytes(&encoded, &mut pos)?;
+          let content_hash = std::str::from_utf8(hash_bytes)
+            .map_err(|e| Error::FastCdcError(e.to_string()))?;
+          let data = base_chunks.get(content_hash).ok_or_else(|| {
+            Error::MissingChunk(content_hash.to_string())
+          })?;
+          after.extend_from_slice(data);
+        }
+        TAG_LITERAL => {
+          after.extend_from_slice(read_bytes(&encoded, &mut pos)?);
+        }
+        other => {
+          return Err(Error::FastCdcError(format!(
+            "unknown fast-cdc patch entry tag: {}",
+            other
+          )));
+        }
+      }
+    }
+
+    let after_hash = crate::hash::hash_with(&after, delta.hash_algorithm);
+    if after_hash != delta.after_hash {
+      return Err(Error::AfterHashMismatch);
+    }
+
+    Ok(after)
+  }
+}
@@ -0,0 +1,222 @@
+//! FSST (Fast Static Symbol Table) compression, backing
+//! `CompressAlgorithm::Fsst` for the many small entries a [`crate::patch::PatchSet`]
+//! tends to carry when diffing a datadir full of tiny, similarly-shaped
+//! files.
+//!
+//! Unlike the other [`crate::compress::CompressAlgorithm`] variants, FSST
+//! isn't self-contained: a single [`SymbolTable`] is trained once across
+//! every small entry in a patch set and stored on the `PatchSet` itself (see
+//! `PatchSet::fsst_table`), so each entry's encoded bytes are only
+//! meaningful alongside that shared table. That's the whole point - per-file
+//! Zstd/LZ4 streams each pay their own framing and dictionary-warmup
+//! overhead, which dominates for files a few hundred bytes long; one shared
+//! table amortizes that cost across every small file in the set instead.
+//!
+//! # Format
+//!
+//! A trained table holds up to [`MAX_SYMBOLS`] byte strings (1-8 bytes
+//! each), indexed by a one-byte code (0..=254). Encoded data is a sequence
+//! of codes; code `0xFF` is reserved as an escape, followed by one literal
+//! byte for input that doesn't match any symbol.
+
+use rkyv::Archive;
+
+use crate::Error;
+
+const ESCAPE: u8 = 0xFF;
+const MAX_SYMBOLS: usize = 255;
+const MAX_SYMBOL_LEN: usize = 8;
+const TRAINING_ROUNDS: usize = 5;
+
+/// A trained FSST symbol table, shared across every entry it was used to
+/// encode. `train` builds one from a set of sample buffers; `encode`/
+/// `decode` use an already-trained table to transform a single buffer.
+#[derive(Archive, rkyv::Deserialize, rkyv::Serialize, Debug, PartialEq, Clone)]
+#[rkyv(derive(Debug, PartialEq, Clone))]
+pub struct SymbolTable {
+  // Indexed by code: `symbols[code]` is the byte string that code expands
+  // to. Never longer than `MAX_SYMBOLS` entries, since a code is one byte
+  // and 0xFF is reserved for the escape.
+  symbols: Vec<Vec<u8>>,
+}
+
+impl SymbolTable {
+  pub(crate) fn symbols(&self) -> &[Vec<u8>] {
+    &self.symbols
+  }
+
+  pub(crate) fn from_symbols(symbols: Vec<Vec<u8>>) -> Self {
+    Self { symbols }
+  }
+
+  // Buckets symbol indices by their first byte, longest-first, so
+  // `longest_match` can try only the symbols that could possibly match at a
+  // given position instead of scanning the whole table. Stands in for the
+  // "lossy perfect hash table keyed on the first 2-3 bytes" a production
+  // FSST implementation would use; this is the same longest-match contract
+  // with a simpler index.
+  fn build_lookup(&self) -> Vec<Vec<usize>> {
+    let mut lookup = vec![Vec::new(); 256];
+    for (code, symbol) in self.symbols.iter().enumerate() {
+      if let Some(&first) = symbol.first() {
+        lookup[first as usize].push(code);
+      }
+    }
+    for bucket in &mut lookup {
+      bucket.sort_by_key(|&code| std::cmp::Reverse(self.symbols[code].len()));
+    }
+    lookup
+  }
+}
+
+fn longest_match(
+  symbols: &[Vec<u8>],
+  lookup: &[Vec<usize>],
+  data: &[u8],
+  pos: usize,
+) -> Option<(u8, usize)> {
+  for &code in &lookup[data[pos] as usize] {
+    let symbol = &symbols[code];
+    if pos + symbol.len() <= data.len() && &data[pos..pos + symbol.len()] == symbol.as_slice() {
+      return Some((code as u8, symbol.len()));
+    }
+  }
+  None
+}
+
+// Seeds the initial candidate pool with every distinct byte that appears in
+// `samples`, most frequent first, capped at `MAX_SYMBOLS`. Training then
+// grows these into longer symbols over `TRAINING_ROUNDS`.
+fn seed_symbols(samples: &[&[u8]]) -> Vec<Vec<u8>> {
+  let mut freq = [0usize; 256];
+  for sample in samples {
+    for &b in *sample {
+      freq[b as usize] += 1;
+    }
+  }
+
+  let mut bytes: Vec<u8> = (0..=255u8).filter(|&b| freq[b as usize] > 0).collect();
+  bytes.sort_by_key(|&b| std::cmp::Reverse(freq[b as usize]));
+  bytes.truncate(MAX_SYMBOLS);
+  bytes.into_iter().map(|b| vec![b]).collect()
+}
+
+/// Trains a symbol table over `samples`: starts from the distinct bytes
+/// present, then over `TRAINING_ROUNDS` greedy rounds, encodes the samples
+/// with the current table, scores every symbol actually used and every
+/// adjacent symbol-pair concatenation by `frequency * length` ("gain"), and
+/// keeps the top [`MAX_SYMBOLS`] candidates for the next round.
+pub(crate) fn train(samples: &[&[u8]]) -> SymbolTable {
+  let mut symbols = seed_symbols(samples);
+
+  for _ in 0..TRAINING_ROUNDS {
+    let table = SymbolTable {
+      symbols: symbols.clone(),
+    };
+    let lookup = table.build_lookup();
+
+    let mut gain: std::collections::HashMap<Vec<u8>, usize> = std::collections::HashMap::new();
+
+    for sample in samples {
+      let mut pos = 0;
+      let mut prev: Option<Vec<u8>> = None;
+      while pos < sample.len() {
+        match longest_match(&table.symbols, &lookup, sample, pos) {
+          Some((code, len)) => {
+            let symbol = table.symbols[code as usize].clone();
+
+            let entry = gain.entry(symbol.clone()).or_insert(0);
+            *entry += symbol.len();
+
+            if let Some(prev_symbol) = &prev {
+              if prev_symbol.len() + symbol.len() <= MAX_SYMBOL_LEN {
+                let mut concat = prev_symbol.clone();
+                concat.extend_from_slice(&symbol);
+                let concat_len = concat.len();
+                let entry = gain.entry(concat).or_insert(0);
+                *entry += concat_len;
+              }
+            }
+
+            prev = Some(symbol);
+            pos += len;
+          }
+          None => {
+            prev = None;
+            pos += 1;
+          }
+        }
+      }
+    }
+
+    let mut ranked: Vec<(Vec<u8>, usize)> = gain.into_iter().collect();
+    // `gain` is a `HashMap`, so its iteration order (and therefore the order
+    // of ties on `(gain, len)`) is randomized per process; break remaining
+    // ties on the symbol bytes themselves so `truncate` below keeps the same
+    // `MAX_SYMBOLS` set - and therefore the same `fsst_table`/encoded bytes/
+    // `operations_hash` - on every run.
+    ranked.sort_by(|a, b| {
+      b.1
+        .cmp(&a.1)
+        .then_with(|| b.0.len().cmp(&a.0.len()))
+        .then_with(|| a.0.cmp(&b.0))
+    });
+    ranked.truncate(MAX_SYMBOLS);
+
+    symbols = ranked.into_iter().map(|(symbol, _)| symbol).collect();
+  }
+
+  SymbolTable { symbols }
+}
+
+/// Encodes `data` against an already-trained `table` using greedy
+/// longest-match: at each position, the longest symbol matching the
+/// remaining bytes is emitted as its one-byte code, or, when no symbol
+/// matches, as the escape byte followed by the literal byte.
+pub(crate) fn encode(table: &SymbolTable, data: &[u8]) -> Vec<u8> {
+  let lookup = table.build_lookup();
+
+  let mut out = Vec::with_capacity(data.len());
+  let mut pos = 0;
+  while pos < data.len() {
+    match longest_match(&table.symbols, &lookup, data, pos) {
+      Some((code, len)) => {
+        out.push(code);
+        pos += len;
+      }
+      None => {
+        out.push(ESCAPE);
+        out.push(data[pos]);
+        pos += 1;
+      }
+    }
+  }
+
+  out
+}
+
+/// Reverses [`encode`]: each non-escape byte is a direct index into
+/// `table`'s symbols, and each escape byte is followed by one literal byte.
+pub(crate) fn decode(table: &SymbolTable, data: &[u8]) -> Result<Vec<u8>, Error> {
+  let mut out = Vec::new();
+  let mut pos = 0;
+  while pos < data.len() {
+    let code = data[pos];
+    pos += 1;
+
+    if code == ESCAPE {
+      let byte = *data
+        .get(pos)
+        .ok_or_else(|| Error::FsstError("truncated fsst escape sequence".to_string()))?;
+      out.push(byte);
+      pos += 1;
+    } else {
+      let symbol = table.symbols.get(code as usize).ok_or_else(|| {
+        Error::FsstError(format!("unknown fsst symbol code: {}", code))
+      })?;
+      out.extend_from_slice(symbol);
+    }
+  }
+
+  Ok(out)
+}
@@ -1,6 +1,11 @@
 use rkyv::Archive;
 
-use crate::{Error, compress::CompressAlgorithm, hash};
+use crate::{
+  Error,
+  compress::CompressAlgorithm,
+  encryption::Encryption,
+  hash::{HashAlgorithm, hash_with},
+};
 
 /// Algorithms available for generating binary diffs.
 ///
@@ -24,7 +29,7 @@ use crate::{Error, compress::CompressAlgorithm, hash};
 ///     b"original",
 ///     b"modified",
 ///     DiffAlgorithm::Bidiff1,
-///     CompressAlgorithm::Zstd
+///     CompressAlgorithm::Zstd { level: 21 }
 /// )?;
 /// # Ok::<(), files_diff::Error>(())
 /// ```
@@ -48,6 +53,34 @@ pub enum DiffAlgorithm {
   /// Bidirectional diff algorithm version 1.
   /// May produce smaller patches for very different files.
   Bidiff1,
+
+  /// Content-defined chunking. Splits each file into variable-length chunks
+  /// and deduplicates them against a shared chunk store instead of
+  /// producing a single whole-file patch. See `Operation::Chunked` and
+  /// `PatchSet::chunk_store`.
+  Cdc,
+
+  /// FastCDC content-defined chunking, version 1. Unlike `Cdc`, this stays
+  /// a single whole-file `Patch` rather than a shared chunk store: `before`
+  /// and `after` are both split into chunks with a Gear-hash rolling
+  /// checksum, and the patch records, per `after` chunk, either a reference
+  /// to a matching chunk already in `before` or the literal bytes when no
+  /// match exists. Much smaller than `Bidiff1` and much faster than
+  /// `Rsync020` on large (40MB+), mostly-similar binary files. See
+  /// `crate::fast_cdc`.
+  FastCdc1,
+
+  /// Content-defined chunking and deduplication, version 1. Like
+  /// `FastCdc1`, a single whole-file `Patch` built from Gear-hash chunks
+  /// rather than a shared chunk store - but with a single fixed cut mask
+  /// instead of `FastCdc1`'s size-normalized two-mask scheme, and chunks
+  /// are interned against both `before` and every chunk already emitted
+  /// from `after`, so a region repeated within `after` itself is also
+  /// deduplicated rather than only ones reused from `before`. A coarser,
+  /// cheaper alternative to `Bidiff1` when a file's changes are large
+  /// moved, repeated, or duplicated regions rather than scattered small
+  /// edits. See `crate::dedup`.
+  Cdc1,
 }
 
 impl std::fmt::Display for DiffAlgorithm {
@@ -56,6 +89,41 @@ impl std::fmt::Display for DiffAlgorithm {
   }
 }
 
+/// Memory/time tradeoff for applying a [`Patch`] or [`PatchSet`], named
+/// after gitoxide's `Algorithm::{LessTime, LessMemory}` split. Both produce
+/// byte-identical output; they only differ in how much of it is held in
+/// memory at once while getting there.
+///
+/// # Example
+/// ```rust
+/// use files_diff::ApplyMode;
+///
+/// // Default to the faster path; only reach for `LessMemory` once a patch
+/// // is large enough that holding the whole result in memory is a problem.
+/// let mode = ApplyMode::LessTime;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ApplyMode {
+  /// Today's behavior: the whole patched result is built up in memory (and,
+  /// for `Operation::Patch`'s rsync case, hashed in one pass at the end)
+  /// before being written out. Fastest, and what every patch other than a
+  /// `DiffAlgorithm::Rsync020` one (e.g. `DiffAlgorithm::Bidiff1`,
+  /// `DiffAlgorithm::FastCdc1`) gets whichever mode is passed.
+  #[default]
+  LessTime,
+  /// Streams the patched result straight to the destination `Write` and
+  /// feeds an incremental hasher (see `crate::hash::IncrementalHash`) as
+  /// bytes become available, rather than buffering the whole thing first.
+  /// Only `DiffAlgorithm::Rsync020` (see `crate::rsync::apply_with`)
+  /// actually streams; other algorithms fall back to `LessTime`'s
+  /// behavior and are applied into a complete buffer first.
+  /// Reading the whole *base* still has to happen up front - `fast_rsync`'s
+  /// public API requires a contiguous `&[u8]` for its random-access copy
+  /// commands - so this bounds the *output* side of memory use, not the
+  /// source file's.
+  LessMemory,
+}
+
 /// A patch that can transform one file into another.
 ///
 /// Contains all the information needed to verify and apply a patch,
@@ -73,10 +141,11 @@ impl std::fmt::Display for DiffAlgorithm {
 ///     source,
 ///     target,
 ///     DiffAlgorithm::Rsync020,
-///     CompressAlgorithm::Zstd
+///     CompressAlgorithm::Zstd { level: 21 }
 /// )?;
 ///
-/// // Verify source hash matches
+/// // Verify source hash matches (verify with the algorithm the patch was
+/// // recorded with, not necessarily MD5)
 /// assert_eq!(files_diff::hash(source), patch.before_hash);
 ///
 /// // Apply patch and verify result
@@ -91,12 +160,28 @@ pub struct Patch {
   pub diff_algorithm: DiffAlgorithm,
   /// Compression method used for the patch data
   pub compress_algorithm: CompressAlgorithm,
-  /// MD5 hash of the source file
+  /// Algorithm `before_hash`/`after_hash` were produced with, so `apply` can
+  /// verify with the algorithm that produced them instead of assuming MD5.
+  pub hash_algorithm: HashAlgorithm,
+  /// Hash of the source file
   pub before_hash: String,
-  /// MD5 hash of the target file
+  /// Hash of the target file
   pub after_hash: String,
   /// The actual patch data
   pub patch: Vec<u8>,
+  /// The rsync signature `block_size` this patch was generated against,
+  /// when `diff_algorithm` is `DiffAlgorithm::Rsync020` (see
+  /// `crate::rsync::SignatureOptions`). Not needed to `apply` the patch -
+  /// only `patch`'s own bytes are - but recorded so a given patch's size and
+  /// generation time can be reproduced and benchmarked later. `None` for
+  /// every other algorithm.
+  pub block_size: Option<u32>,
+  /// Whether `patch` currently holds [`Self::encrypt_payload`]'s AEAD
+  /// ciphertext instead of the plain compressed patch bytes `apply`
+  /// expects. Every diff machine sets this to `false`; call
+  /// [`Self::decrypt_payload`] before handing an encrypted patch to
+  /// `apply` to flip it back.
+  pub encrypted: bool,
 }
 
 impl Patch {
@@ -107,6 +192,9 @@ impl Patch {
       + self.after_hash.len()
       + std::mem::size_of::<CompressAlgorithm>()
       + std::mem::size_of::<DiffAlgorithm>()
+      + std::mem::size_of::<HashAlgorithm>()
+      + std::mem::size_of::<Option<u32>>()
+      + std::mem::size_of::<bool>()
   }
 
   /// Serializes this patch to a byte vector.
@@ -123,11 +211,77 @@ impl Patch {
     rkyv::from_bytes::<_, rkyv::rancor::Error>(bytes)
       .map_err(Error::DeserializeError)
   }
+
+  /// Encrypts `patch` (the bytes `apply` would otherwise hand straight to
+  /// `compress_algorithm` for decompression) in place with a key derived
+  /// from `passphrase`, authenticating the ciphertext with `encryption`'s
+  /// AEAD and setting [`Self::encrypted`] so a caller holding this `Patch`
+  /// can tell it needs [`Self::decrypt_payload`] before `apply` without
+  /// attempting one first. Every other field - `before_hash`/`after_hash`
+  /// included - stays plaintext, so a `Patch` can still be routed and
+  /// inspected without the key; only the payload `apply` would otherwise
+  /// decompress is hidden from a channel an untrusted party might read.
+  /// This is `diff`'s optional encryption step: call it on the `Patch`
+  /// `diff` returned, same as `crate::rsync::diff_with_signature_options`
+  /// is an extra step alongside `diff`'s plain common path rather than a
+  /// new required argument on it.
+  pub fn encrypt_payload(
+    &mut self,
+    passphrase: &str,
+    encryption: Encryption,
+  ) -> Result<(), Error> {
+    self.patch = crate::encryption::encrypt(&self.patch, passphrase, encryption)
+      .map_err(|e| Error::EncryptError(format!("{:?}", e)))?;
+    self.encrypted = true;
+    Ok(())
+  }
+
+  /// Reverses [`Self::encrypt_payload`]: derives the same key from
+  /// `passphrase`, verifies the AEAD tag, and replaces `patch` with the
+  /// recovered plaintext, clearing [`Self::encrypted`]. Call this before
+  /// `apply` on a patch with `encrypted: true`; `apply` itself never sees
+  /// a key, so it always expects `patch` to already be plaintext compressed
+  /// bytes. Fails with [`Error::DecryptError`] if the tag doesn't verify -
+  /// either `passphrase` is wrong, or `patch` was tampered with or
+  /// corrupted in transit.
+  pub fn decrypt_payload(&mut self, passphrase: &str) -> Result<(), Error> {
+    self.patch = crate::encryption::decrypt(&self.patch, passphrase)
+      .map_err(|e| Error::DecryptError(format!("{:?}", e)))?;
+    self.encrypted = false;
+    Ok(())
+  }
 }
 
 /// Type alias for filenames in patch sets
 pub type Filename = String;
 
+/// Per-entry metadata captured from a zip archive entry during `diff_zip`,
+/// replayed by `apply_zip` so timestamps, unix permissions, and the entry's
+/// original compression method survive a diff/apply round-trip instead of
+/// being collapsed to `CompressionMethod::Stored` with a fresh mtime.
+#[derive(Archive, rkyv::Deserialize, rkyv::Serialize, Debug, PartialEq, Clone)]
+#[rkyv(derive(Debug, PartialEq, Clone))]
+pub struct FileMetadata {
+  /// MS-DOS date/time pair, as returned by `zip::DateTime::{datepart,timepart}`.
+  pub last_modified: (u16, u16),
+  /// Unix permission/mode bits, when the entry carries a unix extra field.
+  pub unix_mode: Option<u32>,
+  /// Numeric id of the entry's original `zip::CompressionMethod`.
+  pub compression_method: u16,
+  /// Whether the entry was AES-encrypted in the source archive. When set,
+  /// `apply_zip` re-encrypts the entry with the password supplied to it
+  /// instead of writing it out in the clear.
+  pub encrypted: bool,
+  /// The entry's raw local-file-header extra field (e.g. zipalign padding),
+  /// replayed verbatim by `apply_zip` via
+  /// `zip::write::ZipWriter::start_file_with_extra_data` so a rewritten
+  /// entry's extra field round-trips exactly like an unchanged one
+  /// (`Operation::FileStaysSame`/`CopyFrom`, which already preserve it via
+  /// `raw_copy_file`/`raw_copy_file_rename`). Empty when the entry carried
+  /// none.
+  pub extra_field: Vec<u8>,
+}
+
 /// Operations that can be performed on a file in a patch set.
 ///
 /// Used primarily for zip archive diffing to track changes to individual files
@@ -141,7 +295,7 @@ pub type Filename = String;
 ///     "before.zip".to_string(),
 ///     "after.zip".to_string(),
 ///     DiffAlgorithm::Rsync020,
-///     CompressAlgorithm::Zstd
+///     CompressAlgorithm::Zstd { level: 21 }
 /// )?;
 ///
 /// # Ok::<(), files_diff::Error>(())
@@ -151,12 +305,58 @@ pub type Filename = String;
 pub enum Operation {
   /// File was modified - contains patch to transform it
   Patch(Patch),
-  /// File is new or completely different - contains full file contents
-  PutFile(Vec<u8>),
+  /// File is new or completely different - contains full file contents,
+  /// compressed with `compress_algorithm`.
+  ///
+  /// For every algorithm but `CompressAlgorithm::Fsst` that's the same
+  /// standalone compression `Patch.patch` uses. `Fsst` entries are instead
+  /// encoded against the symbol table stored once in `PatchSet::fsst_table`,
+  /// which `diff_zip` trains across the patch set's small new files.
+  PutFile {
+    compress_algorithm: CompressAlgorithm,
+    data: Vec<u8>,
+  },
   /// File was removed in the target
   DeleteFile,
   /// File is identical in source and target
   FileStaysSame,
+  /// File bytes are identical but the entry's metadata (unix mode or
+  /// mtime) changed, so only the metadata needs to be replayed on apply.
+  MetadataOnly(FileMetadata),
+  /// File's content is byte-identical to an existing entry at a different
+  /// path in the source archive (a rename, move, or duplicate). Carries
+  /// the source path so `apply_zip` can raw-copy it under the new name
+  /// instead of re-adding the full bytes.
+  CopyFrom(Filename),
+  /// File was diffed with `DiffAlgorithm::Cdc`: an ordered list of content
+  /// hashes of the chunks that make up the file. `apply_zip` reassembles
+  /// the file by concatenating each chunk's bytes looked up from
+  /// `PatchSet::chunk_store`.
+  Chunked(Vec<String>),
+  /// File's content is *similar but not identical* to an entry deleted
+  /// from a different path (a renamed or moved file that was also edited).
+  /// Unlike `CopyFrom`, which only ever pairs byte-identical content,
+  /// `from` here names a path that no longer exists in the target archive.
+  /// `apply_zip` reads `from`'s bytes out of the base archive, applies
+  /// `patch` to them if present, and writes the result under the new path,
+  /// which is far smaller than a `DeleteFile` plus a full `PutFile` for a
+  /// large file that only changed slightly. `patch` is `None` for the rare
+  /// case where the match turns out to be byte-identical after all.
+  MoveFile {
+    from: Filename,
+    patch: Option<Patch>,
+  },
+  /// File's content is *similar but not identical* to an entry still
+  /// present at a different path in the target archive (a duplicated file
+  /// that diverged, rather than a rename - unlike `MoveFile`, `source` here
+  /// names a path that's still in use, so it isn't paired with a
+  /// `DeleteFile`). `apply_zip` reads `source`'s bytes out of the base
+  /// archive and applies `patch` to them, which is far smaller than a full
+  /// `PutFile` when the two files mostly overlap.
+  DeltaFrom {
+    source: Filename,
+    patch: Patch,
+  },
 }
 
 impl Operation {
@@ -164,9 +364,16 @@ impl Operation {
   pub fn get_size(&self) -> usize {
     match self {
       Operation::Patch(patch) => patch.get_size(),
-      Operation::PutFile(file) => file.len(),
+      Operation::PutFile { data, .. } => data.len(),
       Operation::DeleteFile => 0,
       Operation::FileStaysSame => 0,
+      Operation::MetadataOnly(_) => std::mem::size_of::<FileMetadata>(),
+      Operation::CopyFrom(source) => source.len(),
+      Operation::Chunked(hashes) => hashes.iter().map(|h| h.len()).sum(),
+      Operation::MoveFile { from, patch } => {
+        from.len() + patch.as_ref().map_or(0, Patch::get_size)
+      }
+      Operation::DeltaFrom { source, patch } => source.len() + patch.get_size(),
     }
   }
 }
@@ -184,8 +391,9 @@ impl Operations {
     )
   }
 
-  pub(crate) fn hash(&self) -> Result<String, Error> {
-    Ok(hash(&self.to_bytes()?))
+  /// Hashes the serialized operations with the given [`HashAlgorithm`].
+  pub(crate) fn hash_with(&self, algorithm: HashAlgorithm) -> Result<String, Error> {
+    Ok(hash_with(&self.to_bytes()?, algorithm))
   }
 }
 
@@ -203,7 +411,7 @@ impl Operations {
 ///     "source.zip".to_string(),
 ///     "target.zip".to_string(),
 ///     DiffAlgorithm::Rsync020,
-///     CompressAlgorithm::Zstd
+///     CompressAlgorithm::Zstd { level: 21 }
 /// )?;
 ///
 /// // Apply all patches to transform the zip
@@ -215,10 +423,34 @@ impl Operations {
 pub struct PatchSet {
   /// The operations that transform the source zip into the target zip
   pub operations: Operations,
+  /// Algorithm `hash_before`/`operations_hash` were produced with, so
+  /// `apply_zip` can verify with the algorithm that produced them instead of
+  /// assuming MD5.
+  pub hash_algorithm: HashAlgorithm,
   /// The hash of the source zip
   pub hash_before: String,
   /// The hash of the operations
   pub operations_hash: String,
+  /// Metadata side-channel, keyed by path, for every entry that has a
+  /// concrete representation in the target archive (added, modified,
+  /// unchanged, or metadata-only). `apply_zip` consults this to restore
+  /// each entry's original compression method, mtime, and unix mode
+  /// instead of hard-coding `CompressionMethod::Stored`.
+  pub metadata: std::collections::HashMap<Filename, FileMetadata>,
+  /// Chunk store for `Operation::Chunked` entries, keyed by chunk content
+  /// hash. Populated only when files were diffed with `DiffAlgorithm::Cdc`;
+  /// empty for patch sets produced by the other algorithms. Deduplicated
+  /// across every chunked file in the set, so an identical chunk shared by
+  /// multiple files is stored once.
+  pub chunk_store: std::collections::HashMap<String, Vec<u8>>,
+  /// Shared FSST symbol table trained across this patch set's small
+  /// `PutFile` entries, present only when `diff_zip` found enough qualifying
+  /// entries to bother training one. Every `Operation::PutFile` whose
+  /// `compress_algorithm` is `CompressAlgorithm::Fsst` was encoded against
+  /// this table; `apply_zip` looks it up from here rather than from the
+  /// individual entry, since FSST's whole point is one table shared across
+  /// many small files instead of one per file. See `crate::fsst`.
+  pub fsst_table: Option<crate::fsst::SymbolTable>,
 }
 
 impl PatchSet {
@@ -232,6 +464,21 @@ impl PatchSet {
       .sum::<usize>()
       + self.hash_before.len()
       + self.operations_hash.len()
+      + std::mem::size_of::<HashAlgorithm>()
+      + self
+        .metadata
+        .iter()
+        .map(|(filename, meta)| filename.len() + std::mem::size_of_val(meta))
+        .sum::<usize>()
+      + self
+        .chunk_store
+        .iter()
+        .map(|(content_hash, bytes)| content_hash.len() + bytes.len())
+        .sum::<usize>()
+      + self
+        .fsst_table
+        .as_ref()
+        .map_or(0, |table| table.symbols().iter().map(Vec::len).sum())
   }
 
   /// Serializes this patch set to a byte vector.
@@ -248,4 +495,41 @@ impl PatchSet {
     rkyv::from_bytes::<_, rkyv::rancor::Error>(bytes)
       .map_err(Error::DeserializeError)
   }
+
+  /// Writes this patch set to `writer` using the versioned binary container
+  /// format (see [`crate::container`]), rather than rkyv's all-at-once
+  /// archive format used by [`Self::to_bytes`]. Operations are written out
+  /// one at a time, so this is the format to reach for when the patch set
+  /// is large enough that holding the whole rkyv-serialized blob in memory
+  /// is undesirable; [`crate::zip::apply_zip_streaming`] reads it back the
+  /// same way.
+  pub fn write_to<W: std::io::Write>(&self, writer: &mut W) -> Result<(), Error> {
+    crate::container::write_to(self, writer)
+  }
+
+  /// Reads a patch set written by [`Self::write_to`] back from `reader`.
+  pub fn read_from<R: std::io::Read>(reader: &mut R) -> Result<Self, Error> {
+    crate::container::read_from(reader)
+  }
+
+  /// Encrypts this patch set's serialized bytes (see [`Self::to_bytes`])
+  /// with a key derived from `passphrase`, authenticating the ciphertext
+  /// with `encryption`'s AEAD so the result can be shipped over an
+  /// untrusted channel and still be reliably rejected if tampered with or
+  /// corrupted. `operations_hash` is still carried inside the encrypted
+  /// bytes, so [`Self::decrypt`] followed by [`apply_zip`](crate::apply_zip)
+  /// checks integrity both cryptographically and structurally. Reverse
+  /// with [`Self::decrypt`].
+  pub fn encrypt(&self, passphrase: &str, encryption: Encryption) -> Result<Vec<u8>, Error> {
+    crate::encryption::encrypt(&self.to_bytes()?, passphrase, encryption)
+  }
+
+  /// Reverses [`Self::encrypt`]: derives the same key from `passphrase`,
+  /// verifies the AEAD tag, and deserializes the patch set from the
+  /// recovered plaintext. Fails with [`Error::AuthenticationFailed`] if the
+  /// tag doesn't verify - either `passphrase` is wrong, or `bytes` was
+  /// tampered with or corrupted.
+  pub fn decrypt(bytes: &[u8], passphrase: &str) -> Result<Self, Error> {
+    Self::from_bytes(&crate::encryption::decrypt(bytes, passphrase)?)
+  }
 }
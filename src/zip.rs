@@ -2,12 +2,58 @@ use crate::{
     apply,
     compress::CompressAlgorithm,
     diff, hash,
-    patch::{DiffAlgorithm, Operation, Operations, PatchSet},
+    hash::{DEFAULT_HASH_ALGORITHM, HashAlgorithm},
+    patch::{ApplyMode, DiffAlgorithm, FileMetadata, Operation, Operations, PatchSet},
     Error,
 };
 use log::{debug, info, trace, warn};
 use std::io::{Read as _, Write};
 
+// Splits `contents` into content-defined chunks, inserting any the store
+// doesn't already have (deduplicating identical chunks across every chunked
+// file in the archive), and returns the ordered list of chunk hashes that
+// reassembles `contents`.
+fn chunk_into_store(
+    contents: &[u8],
+    chunk_store: &mut std::collections::HashMap<String, Vec<u8>>,
+) -> Vec<String> {
+    crate::cdc::chunk(contents)
+        .into_iter()
+        .map(|c| {
+            let content_hash = c.content_hash;
+            chunk_store
+                .entry(content_hash.clone())
+                .or_insert_with(|| contents[c.range].to_vec());
+            content_hash
+        })
+        .collect()
+}
+
+// Dispatches to `diff`, except for `(DiffAlgorithm::Rsync020, Some(_))`,
+// where it calls `crate::rsync::diff_with_signature_options` directly so a
+// pinned `SignatureOptions` (see `diff_zip_with_signature_options`) reaches
+// every per-file diff instead of each one auto-tuning `block_size`
+// independently off its own file's length.
+fn diff_with_signature_options(
+    before: &[u8],
+    after: &[u8],
+    diff_algorithm: DiffAlgorithm,
+    compress_algorithm: CompressAlgorithm,
+    signature_options: Option<crate::rsync::SignatureOptions>,
+) -> Result<crate::patch::Patch, Error> {
+    match (diff_algorithm, signature_options) {
+        (DiffAlgorithm::Rsync020, Some(signature_options)) => {
+            crate::rsync::diff_with_signature_options(
+                before,
+                after,
+                compress_algorithm,
+                Some(signature_options),
+            )
+        }
+        _ => diff(before, after, diff_algorithm, compress_algorithm),
+    }
+}
+
 // Process all files in both archives without recursion
 fn process_directory(
     dir_path: &str,
@@ -15,8 +61,15 @@ fn process_directory(
     files_after: &mut zip::ZipArchive<std::io::Cursor<Vec<u8>>>,
     processed_files: &mut std::collections::HashSet<String>,
     patches: &mut Vec<(String, Operation)>,
+    metadata: &mut std::collections::HashMap<String, FileMetadata>,
+    chunk_store: &mut std::collections::HashMap<String, Vec<u8>>,
+    content_index: &ContentIndex,
     diff_algorithm: DiffAlgorithm,
     compress_algorithm: CompressAlgorithm,
+    password_before: Option<&str>,
+    password_after: Option<&str>,
+    max_threads: Option<usize>,
+    signature_options: Option<crate::rsync::SignatureOptions>,
 ) -> Result<(), Error> {
     debug!("Processing files starting from: `{}`", dir_path);
 
@@ -39,7 +92,18 @@ fn process_directory(
         all_files.insert(file.name().to_string());
     }
 
-    // Process all files
+    // First pass: categorize every path sequentially (zip archives only
+    // support lookup-by-name on a single thread), deferring the CPU-heavy
+    // per-file `diff` calls for modified files to a second pass that can
+    // run in parallel.
+    // The third element is `Some(source)` when the file being diffed against
+    // isn't the same-named before-entry but a similar entry found under a
+    // different path that is still present in the target archive (see
+    // `ContentIndex::find_similar`) - the second pass
+    // below produces `Operation::DeltaFrom` rather than `Operation::Patch`
+    // for those.
+    let mut to_diff: Vec<(String, Option<String>, Vec<u8>, Vec<u8>, FileMetadata)> = Vec::new();
+
     for path in all_files {
         if processed_files.contains(&path) {
             continue;
@@ -52,24 +116,32 @@ fn process_directory(
 
         match (before_exists, after_exists) {
             (true, true) => {
-                // File exists in both archives
-                let before_contents = read_file_contents(files_before, &path)?
-                    .ok_or_else(|| Error::ZipError("Failed to read before contents".to_string()))?;
-                let after_contents = read_file_contents(files_after, &path)?
-                    .ok_or_else(|| Error::ZipError("Failed to read after contents".to_string()))?;
-
-                if before_contents != after_contents {
-                    debug!("File modified: {}", path);
-                    let patch = diff(
-                        &before_contents,
-                        &after_contents,
-                        diff_algorithm,
-                        compress_algorithm,
-                    )?;
-                    patches.push((path, Operation::Patch(patch)));
-                } else {
-                    trace!("File unchanged: {}", path);
-                    patches.push((path, Operation::FileStaysSame));
+                // File exists in both archives. `compare_entry` hashes a
+                // `PARTIAL_HASH_BYTES` prefix of each entry first, which
+                // settles entries no larger than that prefix without a
+                // second read (see its doc comment).
+                match compare_entry(files_before, files_after, &path, password_before, password_after)? {
+                    EntryComparison::Different { before_contents, after_contents, after_meta } => {
+                        debug!("File modified: {}", path);
+                        metadata.insert(path.clone(), after_meta.clone());
+                        if diff_algorithm == DiffAlgorithm::Cdc {
+                            let hashes = chunk_into_store(&after_contents, chunk_store);
+                            patches.push((path, Operation::Chunked(hashes)));
+                        } else {
+                            to_diff.push((path, None, before_contents, after_contents, after_meta));
+                        }
+                    }
+                    EntryComparison::Same { before_meta, after_meta } => {
+                        if before_meta != after_meta {
+                            debug!("File metadata changed: {}", path);
+                            metadata.insert(path.clone(), after_meta.clone());
+                            patches.push((path, Operation::MetadataOnly(after_meta)));
+                        } else {
+                            trace!("File unchanged: {}", path);
+                            metadata.insert(path.clone(), after_meta);
+                            patches.push((path, Operation::FileStaysSame));
+                        }
+                    }
                 }
             }
             (true, false) => {
@@ -80,8 +152,56 @@ fn process_directory(
             (false, true) => {
                 // New file
                 debug!("New file: {}", path);
-                if let Some(contents) = read_file_contents(files_after, &path)? {
-                    patches.push((path, Operation::PutFile(contents)));
+                if let Some((contents, after_meta)) =
+                    read_file_contents_and_metadata(files_after, &path, password_after)?
+                {
+                    metadata.insert(path.clone(), after_meta.clone());
+                    match content_index.find_match(files_before, &contents, password_before)? {
+                        Some(source) => {
+                            debug!("New file {} matches existing entry {}, copying", path, source);
+                            patches.push((path, Operation::CopyFrom(source)));
+                        }
+                        None if diff_algorithm == DiffAlgorithm::Cdc => {
+                            let hashes = chunk_into_store(&contents, chunk_store);
+                            patches.push((path, Operation::Chunked(hashes)));
+                        }
+                        None => {
+                            // Only a source still present in the target can be a
+                            // `DeltaFrom`; a similar entry that was deleted is left
+                            // for `detect_renames` to pair into a `MoveFile`.
+                            match content_index
+                                .find_similar(&contents)
+                                .filter(|(source, _)| files_after.by_name(source).is_ok())
+                            {
+                                Some((source, similarity)) => {
+                                    debug!(
+                                        "New file {} is {:.0}% similar to existing entry {}, delta-encoding",
+                                        path,
+                                        similarity * 100.0,
+                                        source
+                                    );
+                                    let mut source_contents = Vec::new();
+                                    open_entry(files_before, &source, password_before)?
+                                        .ok_or_else(|| {
+                                            Error::ZipError(format!("entry {} disappeared", source))
+                                        })?
+                                        .read_to_end(&mut source_contents)
+                                        .map_err(|e| Error::IoError(e.to_string()))?;
+                                    to_diff.push((path, Some(source), source_contents, contents, after_meta));
+                                }
+                                None => {
+                                    let data = compress_algorithm.compress(&contents)?;
+                                    patches.push((
+                                        path,
+                                        Operation::PutFile {
+                                            compress_algorithm,
+                                            data,
+                                        },
+                                    ));
+                                }
+                            }
+                        }
+                    }
                 }
             }
             (false, false) => {
@@ -91,23 +211,643 @@ fn process_directory(
         }
     }
 
+    // Second pass: run the CPU-heavy per-file diffs. These are independent
+    // of one another, so behind the `parallelism` feature they're farmed out
+    // across a rayon thread pool; otherwise they run sequentially. Either
+    // way the results are sorted by path before being handed back to the
+    // caller (see `diff_zip_impl`), so `operations_hash` never depends on
+    // how many threads did the work or the order they finished in.
+    #[cfg(feature = "parallelism")]
+    let diffed: Vec<Result<(String, Operation), Error>> = {
+        use rayon::prelude::*;
+
+        let run_diffs = |to_diff: Vec<(String, Option<String>, Vec<u8>, Vec<u8>, FileMetadata)>| {
+            to_diff
+                .into_par_iter()
+                .map(|(path, source, before_contents, after_contents, _after_meta)| {
+                    debug!("File modified: {}", path);
+                    let patch = diff_with_signature_options(
+                        &before_contents,
+                        &after_contents,
+                        diff_algorithm,
+                        compress_algorithm,
+                        signature_options,
+                    )?;
+                    let operation = match source {
+                        Some(source) => Operation::DeltaFrom { source, patch },
+                        None => Operation::Patch(patch),
+                    };
+                    Ok((path, operation))
+                })
+                .collect()
+        };
+
+        match max_threads {
+            // A dedicated pool scoped to this call, rather than touching
+            // rayon's global pool, so concurrent diffs in the same process
+            // (e.g. multiple `diff_zip` calls from a server) can each cap
+            // their own thread usage independently.
+            Some(max_threads) => {
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(max_threads)
+                    .build()
+                    .map_err(|e| {
+                        Error::ZipError(format!("failed to build thread pool: {}", e))
+                    })?;
+                pool.install(|| run_diffs(to_diff))
+            }
+            None => run_diffs(to_diff),
+        }
+    };
+
+    #[cfg(not(feature = "parallelism"))]
+    let diffed: Vec<Result<(String, Operation), Error>> = {
+        if max_threads.is_some() {
+            trace!("max_threads has no effect without the `parallelism` feature");
+        }
+        to_diff
+            .into_iter()
+            .map(|(path, source, before_contents, after_contents, _after_meta)| {
+                debug!("File modified: {}", path);
+                let patch = diff_with_signature_options(
+                    &before_contents,
+                    &after_contents,
+                    diff_algorithm,
+                    compress_algorithm,
+                    signature_options,
+                )?;
+                let operation = match source {
+                    Some(source) => Operation::DeltaFrom { source, patch },
+                    None => Operation::Patch(patch),
+                };
+                Ok((path, operation))
+            })
+            .collect()
+    };
+
+    for result in diffed {
+        patches.push(result?);
+    }
+
     Ok(())
 }
 
-// Helper function to read file contents
-fn read_file_contents(
+// Helper function to read both the contents and the metadata of a file
+// entry, in a single `by_name` lookup.
+fn read_file_contents_and_metadata(
+    archive: &mut zip::ZipArchive<std::io::Cursor<Vec<u8>>>,
+    path: &str,
+    password: Option<&str>,
+) -> Result<Option<(Vec<u8>, FileMetadata)>, Error> {
+    let mut file = match open_entry(archive, path, password)? {
+        Some(file) => file,
+        None => return Ok(None),
+    };
+
+    let last_modified = file.last_modified();
+    let metadata = FileMetadata {
+        last_modified: (last_modified.datepart(), last_modified.timepart()),
+        unix_mode: file.unix_mode(),
+        compression_method: file.compression().serialize_to_u16(),
+        encrypted: file.encrypted(),
+        extra_field: file.extra_data().to_vec(),
+    };
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents)
+        .map_err(|e| Error::IoError(e.to_string()))?;
+    Ok(Some((contents, metadata)))
+}
+
+// Outcome of comparing the same path's entry across both archives.
+enum EntryComparison {
+    /// Entries are byte-identical. Metadata still needs to be compared by
+    /// the caller to decide between `FileStaysSame` and `MetadataOnly`.
+    Same {
+        before_meta: FileMetadata,
+        after_meta: FileMetadata,
+    },
+    /// Entries differ. Carries both full contents, since a caller that goes
+    /// on to diff or chunk the file needs them anyway.
+    Different {
+        before_contents: Vec<u8>,
+        after_contents: Vec<u8>,
+        after_meta: FileMetadata,
+    },
+}
+
+// Compares the same-named entry of both archives using a cheap partial-hash
+// pre-check before falling back to a full comparison: first only
+// `PARTIAL_HASH_BYTES` of each entry is read and hashed. A mismatch there
+// proves the entries differ without a full hash (the rest of each entry is
+// still read, since the caller diffs the complete contents). A match only
+// proves the entries *might* be identical (entries longer than the prefix
+// could still differ further in), so it's then confirmed with a full hash of
+// each entry's complete contents. Entries no larger than the prefix are
+// settled by that one partial read; larger entries are read in full either
+// way.
+fn compare_entry(
+    files_before: &mut zip::ZipArchive<std::io::Cursor<Vec<u8>>>,
+    files_after: &mut zip::ZipArchive<std::io::Cursor<Vec<u8>>>,
+    path: &str,
+    password_before: Option<&str>,
+    password_after: Option<&str>,
+) -> Result<EntryComparison, Error> {
+    let (before_prefix, before_is_complete, before_meta) =
+        read_entry_prefix(files_before, path, password_before)?;
+    let (after_prefix, after_is_complete, after_meta) =
+        read_entry_prefix(files_after, path, password_after)?;
+
+    if hash(&before_prefix) != hash(&after_prefix) {
+        let before_contents = if before_is_complete {
+            before_prefix
+        } else {
+            read_full_contents(files_before, path, password_before)?
+        };
+        let after_contents = if after_is_complete {
+            after_prefix
+        } else {
+            read_full_contents(files_after, path, password_after)?
+        };
+        return Ok(EntryComparison::Different {
+            before_contents,
+            after_contents,
+            after_meta,
+        });
+    }
+
+    // Both entries are no larger than the prefix we already read, so the
+    // prefixes *are* the complete contents and we already have our answer.
+    if before_is_complete && after_is_complete {
+        return Ok(EntryComparison::Same {
+            before_meta,
+            after_meta,
+        });
+    }
+
+    // The prefixes matched but at least one entry continues past them;
+    // read the rest and confirm with a full hash before concluding they're
+    // identical.
+    let before_contents = if before_is_complete {
+        before_prefix
+    } else {
+        read_full_contents(files_before, path, password_before)?
+    };
+    let after_contents = if after_is_complete {
+        after_prefix
+    } else {
+        read_full_contents(files_after, path, password_after)?
+    };
+
+    if hash(&before_contents) == hash(&after_contents) {
+        Ok(EntryComparison::Same {
+            before_meta,
+            after_meta,
+        })
+    } else {
+        Ok(EntryComparison::Different {
+            before_contents,
+            after_contents,
+            after_meta,
+        })
+    }
+}
+
+// Reads at most `PARTIAL_HASH_BYTES` of `path`'s entry. The returned `bool`
+// is true when fewer bytes were read than the cap, meaning EOF was reached
+// and the returned bytes are the entry's complete contents.
+fn read_entry_prefix(
     archive: &mut zip::ZipArchive<std::io::Cursor<Vec<u8>>>,
     path: &str,
-) -> Result<Option<Vec<u8>>, Error> {
-    match archive.by_name(path) {
-        Ok(mut file) => {
+    password: Option<&str>,
+) -> Result<(Vec<u8>, bool, FileMetadata), Error> {
+    let file = open_entry(archive, path, password)?
+        .ok_or_else(|| Error::ZipError(format!("entry {} disappeared", path)))?;
+
+    let last_modified = file.last_modified();
+    let metadata = FileMetadata {
+        last_modified: (last_modified.datepart(), last_modified.timepart()),
+        unix_mode: file.unix_mode(),
+        compression_method: file.compression().serialize_to_u16(),
+        encrypted: file.encrypted(),
+        extra_field: file.extra_data().to_vec(),
+    };
+
+    let mut prefix = Vec::new();
+    file.take(PARTIAL_HASH_BYTES as u64)
+        .read_to_end(&mut prefix)
+        .map_err(|e| Error::IoError(e.to_string()))?;
+    let is_complete = prefix.len() < PARTIAL_HASH_BYTES;
+
+    Ok((prefix, is_complete, metadata))
+}
+
+// Re-opens and fully reads `path`'s entry, discarding its metadata (the
+// caller already has it from the preceding `read_entry_prefix` call).
+fn read_full_contents(
+    archive: &mut zip::ZipArchive<std::io::Cursor<Vec<u8>>>,
+    path: &str,
+    password: Option<&str>,
+) -> Result<Vec<u8>, Error> {
+    read_file_contents_and_metadata(archive, path, password)?
+        .map(|(contents, _meta)| contents)
+        .ok_or_else(|| Error::ZipError(format!("entry {} disappeared", path)))
+}
+
+// Opens a zip entry by name, decrypting it with `password` when the entry is
+// AES-encrypted. Returns `Ok(None)` when the entry doesn't exist (mirrors the
+// `by_name`/`Err(_)` handling this replaces) and `Err(DecryptionFailed)` when
+// the entry is encrypted but the password is missing or wrong.
+fn open_entry<'a>(
+    archive: &'a mut zip::ZipArchive<std::io::Cursor<Vec<u8>>>,
+    path: &str,
+    password: Option<&str>,
+) -> Result<Option<zip::read::ZipFile<'a>>, Error> {
+    match password {
+        Some(password) => match archive.by_name_decrypt(path, password.as_bytes()) {
+            Ok(Ok(file)) => Ok(Some(file)),
+            Ok(Err(_)) => Err(Error::DecryptionFailed(path.to_string())),
+            Err(_) => Ok(None),
+        },
+        None => match archive.by_name(path) {
+            Ok(file) => Ok(Some(file)),
+            Err(_) => Ok(None),
+        },
+    }
+}
+
+// Builds the `FileOptions` to write an entry with, replaying its captured
+// metadata when available and falling back to `Stored` with default
+// metadata for patch sets that don't carry it (e.g. directories, or
+// patches produced before metadata capture existed).
+//
+// This replays `last_modified`/`unix_mode`/`compression_method`/`encrypted`
+// exactly. The raw extra field (e.g. zipalign's padding field) is captured
+// separately on `FileMetadata::extra_field` and replayed by `start_entry`
+// below via `start_file_with_extra_data`, so a rewritten entry's extra field
+// round-trips just like an unchanged one written through
+// `raw_copy_file`/`raw_copy_file_rename` (`Operation::FileStaysSame` and
+// `Operation::CopyFrom`).
+fn file_options_for(
+    path: &str,
+    metadata: &std::collections::HashMap<String, FileMetadata>,
+    password_after: Option<&str>,
+) -> zip::write::FileOptions {
+    let default_options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+
+    match metadata.get(path) {
+        Some(meta) => {
+            let mut options = default_options
+                .compression_method(zip::CompressionMethod::parse_from_u16(
+                    meta.compression_method,
+                ))
+                .last_modified_time(
+                    zip::DateTime::try_from_msdos(meta.last_modified.0, meta.last_modified.1)
+                        .unwrap_or_default(),
+                );
+            if let Some(mode) = meta.unix_mode {
+                options = options.unix_permissions(mode);
+            }
+            if meta.encrypted {
+                if let Some(password) = password_after {
+                    options = options.with_aes_encryption(zip::AesMode::Aes256, password);
+                }
+            }
+            options
+        }
+        None => default_options,
+    }
+}
+
+// Starts a new entry in `new_archive`, replaying the captured raw extra
+// field (if any) alongside the regular `FileOptions`. `ZipWriter::start_file`
+// alone always starts an entry with an empty extra field, so any captured
+// `FileMetadata::extra_field` bytes - zipalign padding, Info-ZIP Unix fields,
+// and so on - have to be written through `start_file_with_extra_data` plus
+// `end_extra_data` instead.
+fn start_entry(
+    new_archive: &mut zip::ZipWriter<std::fs::File>,
+    path: &str,
+    options: zip::write::FileOptions,
+    metadata: &std::collections::HashMap<String, FileMetadata>,
+) -> Result<(), Error> {
+    match metadata.get(path) {
+        Some(meta) if !meta.extra_field.is_empty() => {
+            new_archive
+                .start_file_with_extra_data(path, options)
+                .map_err(|e| Error::ZipError(e.to_string()))?;
+            new_archive
+                .write_all(&meta.extra_field)
+                .map_err(|e| Error::IoError(e.to_string()))?;
+            new_archive
+                .end_extra_data()
+                .map_err(|e| Error::ZipError(e.to_string()))?;
+            Ok(())
+        }
+        _ => new_archive
+            .start_file(path, options)
+            .map_err(|e| Error::ZipError(e.to_string())),
+    }
+}
+
+// Number of leading bytes hashed for the cheap first pass of rename/copy
+// detection, before falling back to a full content hash to confirm a match.
+const PARTIAL_HASH_BYTES: usize = 4096;
+
+fn partial_hash(contents: &[u8]) -> String {
+    hash(&contents[..contents.len().min(PARTIAL_HASH_BYTES)])
+}
+
+// Buckets every entry of a source archive by (length, partial hash) so an
+// "added" file elsewhere can cheaply be checked for a byte-identical match —
+// a rename, move, or duplicate — without fully hashing every base entry up
+// front. Only entries that collide on the cheap key pay for a full content
+// hash to confirm identity.
+struct ContentIndex {
+    buckets: std::collections::HashMap<(usize, String), Vec<String>>,
+    // Content-defined chunk hashes per entry, for `find_similar`'s
+    // near-duplicate search. Built alongside `buckets` so both an exact and
+    // a fuzzy match can be tried against the same single archive pass.
+    chunks: std::collections::HashMap<String, std::collections::HashSet<String>>,
+}
+
+impl ContentIndex {
+    fn build(
+        archive: &mut zip::ZipArchive<std::io::Cursor<Vec<u8>>>,
+        password: Option<&str>,
+    ) -> Result<Self, Error> {
+        let mut buckets: std::collections::HashMap<(usize, String), Vec<String>> =
+            std::collections::HashMap::new();
+        let mut chunks: std::collections::HashMap<String, std::collections::HashSet<String>> =
+            std::collections::HashMap::new();
+
+        let names: Vec<String> = archive.file_names().map(|name| name.to_string()).collect();
+        for name in names {
+            let mut file = match open_entry(archive, &name, password)? {
+                Some(file) => file,
+                None => continue,
+            };
+            if file.is_dir() {
+                continue;
+            }
             let mut contents = Vec::new();
             file.read_to_end(&mut contents)
                 .map_err(|e| Error::IoError(e.to_string()))?;
-            Ok(Some(contents))
+
+            let key = (contents.len(), partial_hash(&contents));
+            buckets.entry(key).or_default().push(name.clone());
+
+            let content_chunks = crate::cdc::chunk(&contents)
+                .into_iter()
+                .map(|c| c.content_hash)
+                .collect();
+            chunks.insert(name, content_chunks);
+        }
+
+        Ok(Self { buckets, chunks })
+    }
+
+    // Returns the path of a source entry whose full content hash matches
+    // `contents`, if any such entry exists.
+    fn find_match(
+        &self,
+        archive: &mut zip::ZipArchive<std::io::Cursor<Vec<u8>>>,
+        contents: &[u8],
+        password: Option<&str>,
+    ) -> Result<Option<String>, Error> {
+        let key = (contents.len(), partial_hash(contents));
+        let candidates = match self.buckets.get(&key) {
+            Some(candidates) => candidates,
+            None => return Ok(None),
+        };
+
+        let target_hash = hash(contents);
+        for candidate in candidates {
+            let mut file = open_entry(archive, candidate, password)?
+                .ok_or_else(|| Error::ZipError(format!("entry {} disappeared", candidate)))?;
+            let mut candidate_contents = Vec::new();
+            file.read_to_end(&mut candidate_contents)
+                .map_err(|e| Error::IoError(e.to_string()))?;
+
+            if hash(&candidate_contents) == target_hash {
+                return Ok(Some(candidate.clone()));
+            }
+        }
+
+        Ok(None)
+    }
+
+    // Returns the path and Jaccard similarity of the source entry whose
+    // content-defined chunks overlap `contents`' chunks the most, if any
+    // entry clears `RENAME_SIMILARITY_THRESHOLD`. Unlike `find_match`, pure
+    // lookup against the chunk sets computed in `build` - no archive access
+    // needed, since an exact match (which would need confirming against
+    // real bytes) is always tried first via `find_match`.
+    fn find_similar(&self, contents: &[u8]) -> Option<(String, f64)> {
+        let target_chunks: std::collections::HashSet<String> = crate::cdc::chunk(contents)
+            .into_iter()
+            .map(|c| c.content_hash)
+            .collect();
+
+        let mut best: Option<(String, f64)> = None;
+        for (path, candidate_chunks) in &self.chunks {
+            let intersection = target_chunks.intersection(candidate_chunks).count();
+            if intersection == 0 {
+                continue;
+            }
+            let union = target_chunks.union(candidate_chunks).count();
+            let similarity = intersection as f64 / union as f64;
+            if similarity >= RENAME_SIMILARITY_THRESHOLD
+                && best.as_ref().map_or(true, |(_, best_similarity)| similarity > *best_similarity)
+            {
+                best = Some((path.clone(), similarity));
+            }
+        }
+
+        best
+    }
+}
+
+// Minimum fraction of content-defined chunks two files must share (by
+// count, Jaccard similarity over their chunk hash sets) before a deleted
+// file and an unmatched new file are treated as a rename/move rather than
+// two independent operations. Picked loosely: high enough that unrelated
+// files sharing a handful of chunks by coincidence don't get paired, low
+// enough to still catch a renamed file that also picked up real edits.
+const RENAME_SIMILARITY_THRESHOLD: f64 = 0.5;
+
+// Scans `patches` for `DeleteFile`/`PutFile` pairs whose content is similar
+// but not identical and replaces each matched pair with a single
+// `Operation::MoveFile` carrying a real diff between the old and new
+// content. Byte-identical renames never reach this function: `ContentIndex`
+// already turns those into `Operation::CopyFrom` while `patches` is being
+// built, before any `DeleteFile` for the same content is even considered.
+//
+// Similarity is estimated by chunking both files with the same
+// content-defined chunker `DiffAlgorithm::Cdc` uses and comparing chunk
+// hash sets, which is much cheaper than running a full diff against every
+// candidate. Matching is greedy: each deleted file pairs with at most one
+// new file, whichever unmatched candidate has the highest overlap above
+// `RENAME_SIMILARITY_THRESHOLD`.
+//
+// Skipped entirely under `DiffAlgorithm::Cdc`, where renamed files already
+// share chunks with their previous content via the patch set's
+// `chunk_store` without needing a dedicated `MoveFile` operation.
+fn detect_renames(
+    patches: &mut Vec<(String, Operation)>,
+    files_before: &mut zip::ZipArchive<std::io::Cursor<Vec<u8>>>,
+    password_before: Option<&str>,
+    diff_algorithm: DiffAlgorithm,
+    compress_algorithm: CompressAlgorithm,
+    signature_options: Option<crate::rsync::SignatureOptions>,
+) -> Result<(), Error> {
+    if diff_algorithm == DiffAlgorithm::Cdc {
+        return Ok(());
+    }
+
+    let deleted_paths: Vec<String> = patches
+        .iter()
+        .filter(|(_, op)| matches!(op, Operation::DeleteFile))
+        .map(|(path, _)| path.clone())
+        .collect();
+
+    if deleted_paths.is_empty() {
+        return Ok(());
+    }
+
+    let mut deleted_candidates: Vec<(String, Vec<u8>, std::collections::HashSet<String>)> =
+        Vec::new();
+    for path in deleted_paths {
+        let mut file = match open_entry(files_before, &path, password_before)? {
+            Some(file) => file,
+            None => continue,
+        };
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)
+            .map_err(|e| Error::IoError(e.to_string()))?;
+        let chunks = crate::cdc::chunk(&contents)
+            .into_iter()
+            .map(|c| c.content_hash)
+            .collect();
+        deleted_candidates.push((path, contents, chunks));
+    }
+
+    let mut matched_deleted: std::collections::HashSet<String> = std::collections::HashSet::new();
+
+    for i in 0..patches.len() {
+        let (put_compress_algorithm, data) = match &patches[i].1 {
+            Operation::PutFile { compress_algorithm, data } => (*compress_algorithm, data.clone()),
+            _ => continue,
+        };
+        let after_contents = put_compress_algorithm.decompress(&data)?;
+        let after_chunks: std::collections::HashSet<String> = crate::cdc::chunk(&after_contents)
+            .into_iter()
+            .map(|c| c.content_hash)
+            .collect();
+
+        let mut best: Option<(usize, f64)> = None;
+        for (idx, (candidate_path, _, candidate_chunks)) in deleted_candidates.iter().enumerate() {
+            if matched_deleted.contains(candidate_path) {
+                continue;
+            }
+            let intersection = after_chunks.intersection(candidate_chunks).count();
+            if intersection == 0 {
+                continue;
+            }
+            let union = after_chunks.union(candidate_chunks).count();
+            let similarity = intersection as f64 / union as f64;
+            if similarity >= RENAME_SIMILARITY_THRESHOLD
+                && best.map_or(true, |(_, best_similarity)| similarity > best_similarity)
+            {
+                best = Some((idx, similarity));
+            }
+        }
+
+        if let Some((idx, similarity)) = best {
+            let (from, before_contents, _) = &deleted_candidates[idx];
+            debug!(
+                "Detected rename: {} -> {} ({:.0}% of chunks shared)",
+                from,
+                patches[i].0,
+                similarity * 100.0
+            );
+            let patch = diff_with_signature_options(
+                before_contents,
+                &after_contents,
+                diff_algorithm,
+                compress_algorithm,
+                signature_options,
+            )?;
+            matched_deleted.insert(from.clone());
+            patches[i].1 = Operation::MoveFile {
+                from: from.clone(),
+                patch: Some(patch),
+            };
+        }
+    }
+
+    if !matched_deleted.is_empty() {
+        patches.retain(|(path, op)| {
+            !(matches!(op, Operation::DeleteFile) && matched_deleted.contains(path))
+        });
+    }
+
+    Ok(())
+}
+
+// `PutFile` entries at or under this size are the ones FSST actually helps:
+// a per-file Zstd/LZ4 stream's own framing and dictionary warmup dominates
+// at this size, while a shared symbol table amortizes across every small
+// entry instead.
+const FSST_MAX_ENTRY_SIZE: usize = 4096;
+
+// Training a symbol table only pays off with enough small entries to share
+// it across; below this count, leave `PutFile` entries compressed the way
+// they already are rather than spend the training pass for no benefit.
+const FSST_MIN_CANDIDATE_ENTRIES: usize = 8;
+
+// Scans `patches` for `PutFile` entries no larger than `FSST_MAX_ENTRY_SIZE`
+// and, if there are at least `FSST_MIN_CANDIDATE_ENTRIES` of them, trains a
+// single `fsst::SymbolTable` across their decompressed content and
+// re-encodes each one against it, switching its `compress_algorithm` to
+// `CompressAlgorithm::Fsst`. Returns the trained table so the caller can
+// store it on the resulting `PatchSet`; returns `None` (leaving `patches`
+// untouched) when too few entries qualify.
+fn apply_fsst_dictionary(
+    patches: &mut [(String, Operation)],
+) -> Result<Option<crate::fsst::SymbolTable>, Error> {
+    let mut candidates = Vec::new();
+    for (index, (_, op)) in patches.iter().enumerate() {
+        if let Operation::PutFile { compress_algorithm: entry_compress_algorithm, data } = op {
+            if data.len() <= FSST_MAX_ENTRY_SIZE {
+                let contents = entry_compress_algorithm.decompress(data)?;
+                candidates.push((index, contents));
+            }
         }
-        Err(_) => Ok(None),
     }
+
+    if candidates.len() < FSST_MIN_CANDIDATE_ENTRIES {
+        return Ok(None);
+    }
+
+    let samples: Vec<&[u8]> = candidates.iter().map(|(_, contents)| contents.as_slice()).collect();
+    let table = crate::fsst::train(&samples);
+
+    debug!(
+        "Trained FSST dictionary over {} small entries ({} symbols)",
+        candidates.len(),
+        table.symbols().len()
+    );
+
+    for (index, contents) in &candidates {
+        let encoded = crate::fsst::encode(&table, contents);
+        patches[*index].1 = Operation::PutFile {
+            compress_algorithm: CompressAlgorithm::Fsst,
+            data: encoded,
+        };
+    }
+
+    Ok(Some(table))
 }
 
 fn get_directories_of_file(path: &str) -> Vec<String> {
@@ -144,7 +884,7 @@ fn get_directories_of_file(path: &str) -> Vec<String> {
 ///     "v1.zip".to_string(),
 ///     "v2.zip".to_string(),
 ///     DiffAlgorithm::Rsync020,
-///     CompressAlgorithm::Zstd
+///     CompressAlgorithm::Zstd { level: 21 }
 /// )?;
 ///
 /// // Check the total size of all patches
@@ -162,6 +902,108 @@ pub fn diff_zip(
     path_after: String,
     diff_algorithm: DiffAlgorithm,
     compress_algorithm: CompressAlgorithm,
+) -> Result<PatchSet, Error> {
+    crate::archive::diff_archive(
+        crate::archive::ArchiveFormat::Zip,
+        path_before,
+        path_after,
+        diff_algorithm,
+        compress_algorithm,
+    )
+}
+
+/// Same as [`diff_zip`], but for password-protected (AES-encrypted) zip
+/// archives. `password_before`/`password_after` decrypt the respective
+/// archive's entries so they can be diffed by plaintext; an entry that isn't
+/// encrypted ignores the password it's passed.
+pub fn diff_zip_encrypted(
+    path_before: String,
+    path_after: String,
+    diff_algorithm: DiffAlgorithm,
+    compress_algorithm: CompressAlgorithm,
+    password_before: Option<&str>,
+    password_after: Option<&str>,
+) -> Result<PatchSet, Error> {
+    diff_zip_impl(
+        path_before,
+        path_after,
+        diff_algorithm,
+        compress_algorithm,
+        password_before,
+        password_after,
+        None,
+        None,
+    )
+}
+
+/// Same as [`diff_zip`], but caps the number of threads used for the
+/// parallel per-file diff pass (behind the `parallelism` feature; see
+/// `process_directory`) at `max_threads` instead of rayon's default global
+/// pool. Passing `None` is identical to [`diff_zip`]. Useful for
+/// reproducible benchmarking, or to avoid a large archive diff starving
+/// other work on a shared machine; has no effect without the
+/// `parallelism` feature, since diffing then already runs on the calling
+/// thread.
+pub fn diff_zip_with_thread_limit(
+    path_before: String,
+    path_after: String,
+    diff_algorithm: DiffAlgorithm,
+    compress_algorithm: CompressAlgorithm,
+    max_threads: Option<usize>,
+) -> Result<PatchSet, Error> {
+    diff_zip_impl(
+        path_before,
+        path_after,
+        diff_algorithm,
+        compress_algorithm,
+        None,
+        None,
+        max_threads,
+        None,
+    )
+}
+
+/// Same as [`diff_zip`], but for `diff_algorithm: DiffAlgorithm::Rsync020`
+/// lets the caller pin `signature_options` instead of letting every per-file
+/// diff auto-tune its own `block_size` from that file's length (see
+/// `crate::rsync::diff_with_signature_options`). Mirrors
+/// [`diff_zip_with_thread_limit`]'s shape: the common path (`diff_zip`)
+/// stays a plain call, and this is the escape hatch for callers - and the
+/// benchmark harness - that want to reproduce or sweep a specific block
+/// size across a whole archive. Has no effect when `diff_algorithm` isn't
+/// `DiffAlgorithm::Rsync020`.
+pub fn diff_zip_with_signature_options(
+    path_before: String,
+    path_after: String,
+    diff_algorithm: DiffAlgorithm,
+    compress_algorithm: CompressAlgorithm,
+    signature_options: Option<crate::rsync::SignatureOptions>,
+) -> Result<PatchSet, Error> {
+    diff_zip_impl(
+        path_before,
+        path_after,
+        diff_algorithm,
+        compress_algorithm,
+        None,
+        None,
+        None,
+        signature_options,
+    )
+}
+
+// Zip-specific implementation backing both `diff_zip` and
+// `diff_archive(ArchiveFormat::Zip, ..)`. Kept separate from the
+// format-generic archive machinery so the rename/copy detection, metadata
+// replay, and raw-copy optimizations above stay zip-specific.
+pub(crate) fn diff_zip_impl(
+    path_before: String,
+    path_after: String,
+    diff_algorithm: DiffAlgorithm,
+    compress_algorithm: CompressAlgorithm,
+    password_before: Option<&str>,
+    password_after: Option<&str>,
+    max_threads: Option<usize>,
+    signature_options: Option<crate::rsync::SignatureOptions>,
 ) -> Result<PatchSet, Error> {
     info!("Generating diff between {} and {}", path_before, path_after);
     debug!("Using diff algorithm: {:?}", diff_algorithm);
@@ -172,7 +1014,7 @@ pub fn diff_zip(
     let after = std::fs::read(path_after).map_err(|e| Error::IoError(e.to_string()))?;
     info!("after size: {}", after.len());
 
-    let hash_before = hash(&before);
+    let hash_before = crate::hash::hash_with(&before, DEFAULT_HASH_ALGORITHM);
 
     trace!("Before archive size: {} bytes", before.len());
     trace!("After archive size: {} bytes", after.len());
@@ -184,6 +1026,9 @@ pub fn diff_zip(
 
     let mut patches = Vec::new();
     let mut processed_files = std::collections::HashSet::new();
+    let mut metadata = std::collections::HashMap::new();
+    let mut chunk_store = std::collections::HashMap::new();
+    let content_index = ContentIndex::build(&mut files_before, password_before)?;
 
     // Start processing from root
     process_directory(
@@ -192,18 +1037,47 @@ pub fn diff_zip(
         &mut files_after,
         &mut processed_files,
         &mut patches,
+        &mut metadata,
+        &mut chunk_store,
+        &content_index,
+        diff_algorithm,
+        compress_algorithm,
+        password_before,
+        password_after,
+        max_threads,
+        signature_options,
+    )?;
+
+    // Pair up remaining deletions with unmatched new files that are similar
+    // (but not identical - those were already folded into `CopyFrom` above)
+    // so a move/rename only costs a delta instead of a full delete + upload.
+    detect_renames(
+        &mut patches,
+        &mut files_before,
+        password_before,
         diff_algorithm,
         compress_algorithm,
+        signature_options,
     )?;
 
+    // Sorting by path makes `operations_hash` independent of the order the
+    // parallel diff pass happens to finish its work in.
+    patches.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let fsst_table = apply_fsst_dictionary(&mut patches)?;
+
     let operations = Operations(patches);
-    let operations_hash = operations.hash()?;
+    let operations_hash = operations.hash_with(DEFAULT_HASH_ALGORITHM)?;
 
     debug!("Generated {} patch operations", operations.0.len());
     Ok(PatchSet {
         operations,
+        hash_algorithm: DEFAULT_HASH_ALGORITHM,
         hash_before,
         operations_hash,
+        metadata,
+        chunk_store,
+        fsst_table,
     })
 }
 
@@ -222,7 +1096,7 @@ pub fn diff_zip(
 ///     "source.zip".to_string(),
 ///     "target.zip".to_string(),
 ///     DiffAlgorithm::Rsync020,
-///     CompressAlgorithm::Zstd
+///     CompressAlgorithm::Zstd { level: 21 }
 /// )?;
 ///
 /// // Apply the patches to create a new version
@@ -240,17 +1114,106 @@ pub fn diff_zip(
 /// - Maintains file metadata
 /// - Validates all operations during application
 pub fn apply_zip(path_base: &str, delta: PatchSet, path_after: String) -> Result<(), Error> {
+    crate::archive::apply_archive(
+        crate::archive::ArchiveFormat::Zip,
+        path_base,
+        delta,
+        path_after,
+    )
+}
+
+/// Same as [`apply_zip`], but `encrypted_patch` is the output of
+/// [`PatchSet::encrypt`] rather than a plain [`PatchSet`]. The AEAD tag is
+/// verified (and the patch set decrypted) up front, so a wrong `passphrase`
+/// or a tampered/corrupted blob is rejected before anything is applied
+/// rather than partway through. `operations_hash` is still checked
+/// afterwards exactly like [`apply_zip`], so integrity is enforced both
+/// cryptographically (the AEAD tag) and structurally (the hash).
+///
+/// `password_base`/`password_after` are unrelated to `passphrase`: they
+/// decrypt/re-encrypt AES-protected zip entries, same as
+/// [`apply_zip_encrypted`].
+pub fn apply_zip_with_encrypted_patch(
+    path_base: &str,
+    encrypted_patch: &[u8],
+    path_after: String,
+    passphrase: &str,
+    password_base: Option<&str>,
+    password_after: Option<&str>,
+) -> Result<(), Error> {
+    let delta = PatchSet::decrypt(encrypted_patch, passphrase)?;
+    apply_zip_impl(
+        path_base,
+        delta,
+        path_after,
+        password_base,
+        password_after,
+        ApplyMode::LessTime,
+    )
+}
+
+/// Same as [`apply_zip`], but for password-protected (AES-encrypted) zip
+/// archives. `password_base` decrypts entries read back out of the base
+/// archive (needed to patch or replay metadata for them); `password_after`
+/// re-encrypts entries in the output archive that were encrypted in their
+/// source.
+pub fn apply_zip_encrypted(
+    path_base: &str,
+    delta: PatchSet,
+    path_after: String,
+    password_base: Option<&str>,
+    password_after: Option<&str>,
+) -> Result<(), Error> {
+    apply_zip_impl(
+        path_base,
+        delta,
+        path_after,
+        password_base,
+        password_after,
+        ApplyMode::LessTime,
+    )
+}
+
+/// Same as [`apply_zip`], but in `ApplyMode::LessMemory` streams each
+/// `Operation::Patch`/`Operation::DeltaFrom`/`Operation::MoveFile` entry's
+/// rsync-patched content directly into the output archive instead of
+/// building the whole patched entry in memory first (see
+/// `crate::rsync::apply_with`). Every other operation kind, and every
+/// non-`DiffAlgorithm::Rsync020` patch, is unaffected by `mode` - they
+/// already write one entry at a time either way, and non-rsync patches are
+/// applied into a complete buffer before being written. Passing
+/// `ApplyMode::LessTime` is identical
+/// to [`apply_zip`].
+pub fn apply_zip_with(
+    path_base: &str,
+    delta: PatchSet,
+    path_after: String,
+    mode: ApplyMode,
+) -> Result<(), Error> {
+    apply_zip_impl(path_base, delta, path_after, None, None, mode)
+}
+
+// Zip-specific implementation backing both `apply_zip` and
+// `apply_archive(ArchiveFormat::Zip, ..)`.
+pub(crate) fn apply_zip_impl(
+    path_base: &str,
+    delta: PatchSet,
+    path_after: String,
+    password_base: Option<&str>,
+    password_after: Option<&str>,
+    mode: ApplyMode,
+) -> Result<(), Error> {
     info!("Applying patch to {} to create {}", path_base, path_after);
     debug!("Patch contains {} operations", delta.operations.0.len());
 
     let base_data = std::fs::read(path_base).map_err(|e| Error::IoError(e.to_string()))?;
 
-    let base_hash = hash(&base_data);
+    let base_hash = crate::hash::hash_with(&base_data, delta.hash_algorithm);
     if base_hash != delta.hash_before {
         return Err(Error::BeforeHashMismatch);
     }
 
-    if delta.operations_hash != delta.operations.hash()? {
+    if delta.operations_hash != delta.operations.hash_with(delta.hash_algorithm)? {
         return Err(Error::OperationsHashMismatch);
     }
 
@@ -260,108 +1223,320 @@ pub fn apply_zip(path_base: &str, delta: PatchSet, path_after: String) -> Result
     let file = std::fs::File::create(&path_after).map_err(|e| Error::IoError(e.to_string()))?;
 
     let mut new_archive = zip::ZipWriter::new(file);
-    let options =
-        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
-
-    // Track processed files to handle deletions
-    let mut processed_files = std::collections::HashSet::new();
     let mut directories_to_create: std::collections::HashSet<String> =
         std::collections::HashSet::new();
 
     // First, apply all patches
     for (path, operation) in delta.operations.0 {
-        processed_files.insert(path.clone());
-
-        match operation {
-            Operation::Patch(patch) => {
-                debug!("Applying patch to file: {}", path);
-                // Read original file
-                let mut base_file = base_archive
-                    .by_name(&path)
-                    .map_err(|e| Error::ZipError(e.to_string()))?;
-                let mut original_contents = Vec::new();
-                base_file
-                    .read_to_end(&mut original_contents)
-                    .map_err(|e| Error::IoError(e.to_string()))?;
+        apply_operation(
+            &mut base_archive,
+            &mut new_archive,
+            &path,
+            operation,
+            &delta.metadata,
+            &delta.chunk_store,
+            delta.fsst_table.as_ref(),
+            password_base,
+            password_after,
+            mode,
+            &mut directories_to_create,
+        )?;
+    }
 
-                // Apply patch to get new contents
-                let new_contents = apply(&original_contents, &patch)?;
+    finish_archive(new_archive, directories_to_create, &path_after)
+}
 
-                // Write new file
-                new_archive
-                    .start_file(&path, options)
-                    .map_err(|e| Error::ZipError(e.to_string()))?;
-                new_archive
-                    .write_all(&new_contents)
-                    .map_err(|e| Error::IoError(e.to_string()))?;
-                directories_to_create.extend(get_directories_of_file(&path));
-            }
-            Operation::PutFile(contents) => {
-                debug!("Adding new file: {}", path);
-                // Write new file directly
-                new_archive
-                    .start_file(&path, options)
-                    .map_err(|e| Error::ZipError(e.to_string()))?;
-                new_archive
-                    .write_all(&contents)
-                    .map_err(|e| Error::IoError(e.to_string()))?;
-                directories_to_create.extend(get_directories_of_file(&path));
-            }
-            Operation::DeleteFile => {
-                debug!("Deleting file: {}", path);
-                // Skip this file - don't copy it to new archive
-                continue;
-            }
-            Operation::FileStaysSame => {
-                debug!("File stays same: {}", path);
-                // Copy file from base archive
-                // Copy file contents in a single operation
-                let mut contents = Vec::new();
-                base_archive
-                    .by_name(&path)
-                    .map_err(|e| Error::ZipError(e.to_string()))?
-                    .read_to_end(&mut contents)
-                    .map_err(|e| Error::IoError(e.to_string()))?;
+/// Same as [`apply_zip`], but reads the patch set from `patch_reader` using
+/// the self-describing container format written by [`PatchSet::write_to`]
+/// rather than an in-memory [`PatchSet`], and applies one operation at a
+/// time as it's read instead of collecting them into a `Vec` first. Peak
+/// memory is therefore bounded by the largest single operation rather than
+/// the whole patch set, which matters for multi-gigabyte patches.
+///
+/// `hash_before` is verified against the base archive exactly like
+/// [`apply_zip`]. `operations_hash` is *not* verified here: confirming it
+/// would mean buffering every operation into memory to reproduce the exact
+/// bytes it was hashed from, which defeats the purpose of streaming. Each
+/// `Operation::Patch`'s own `before_hash`/`after_hash` is still checked by
+/// `apply`, so corruption of an individual patch is still caught.
+pub fn apply_zip_streaming<R: std::io::Read>(
+    path_base: &str,
+    patch_reader: &mut R,
+    path_after: String,
+    password_base: Option<&str>,
+    password_after: Option<&str>,
+    mode: ApplyMode,
+) -> Result<(), Error> {
+    info!(
+        "Applying streamed patch to {} to create {}",
+        path_base, path_after
+    );
 
-                new_archive
-                    .start_file(&path, options)
-                    .map_err(|e| Error::ZipError(e.to_string()))?;
-                new_archive
-                    .write_all(&contents)
-                    .map_err(|e| Error::IoError(e.to_string()))?;
-                directories_to_create.extend(get_directories_of_file(&path));
-            }
-        }
-    }
+    let base_data = std::fs::read(path_base).map_err(|e| Error::IoError(e.to_string()))?;
 
-    for dir in directories_to_create {
-        trace!("creating directory {}", dir);
+    let header = crate::container::read_header(patch_reader)?;
 
-        new_archive
-            .add_directory(dir, options)
-            .map_err(|e| Error::ZipError(e.to_string()))?;
+    let base_hash = crate::hash::hash_with(&base_data, header.hash_algorithm);
+    if base_hash != header.hash_before {
+        return Err(Error::BeforeHashMismatch);
     }
 
-    // Finalize the ZIP file
-    new_archive
-        .finish()
+    let mut base_archive = zip::ZipArchive::new(std::io::Cursor::new(base_data))
         .map_err(|e| Error::ZipError(e.to_string()))?;
 
-    info!("Successfully created patched archive: {}", path_after);
-    Ok(())
-}
+    let file = std::fs::File::create(&path_after).map_err(|e| Error::IoError(e.to_string()))?;
 
-#[cfg(test)]
-mod tests {
-    use crate::patch::Patch;
+    let mut new_archive = zip::ZipWriter::new(file);
+    let mut directories_to_create: std::collections::HashSet<String> =
+        std::collections::HashSet::new();
 
-    use super::*;
-    use pretty_assertions::assert_eq;
-    use std::fs;
-    use std::io::{Read, Write};
-    use tempfile::TempDir;
+    for _ in 0..header.operation_count {
+        let (path, operation) = crate::container::read_next_operation(patch_reader)?;
+        apply_operation(
+            &mut base_archive,
+            &mut new_archive,
+            &path,
+            operation,
+            &header.metadata,
+            &header.chunk_store,
+            header.fsst_table.as_ref(),
+            password_base,
+            password_after,
+            mode,
+            &mut directories_to_create,
+        )?;
+    }
 
-    use std::sync::Once;
+    finish_archive(new_archive, directories_to_create, &path_after)
+}
+
+// Applies a single (path, operation) pair: reads whatever it needs from
+// `base_archive` and writes the resulting entry into `new_archive`. Shared
+// by `apply_zip_impl` (operations already collected into a `Vec`) and
+// `apply_zip_streaming` (operations read one at a time from a container),
+// so the two apply paths can't drift apart.
+// Applies `patch` to `original_contents`, writing the result into whichever
+// entry `new_archive` currently has open (i.e. right after `start_file`).
+// In `ApplyMode::LessMemory`, a `DiffAlgorithm::Rsync020` patch streams
+// straight into `new_archive` via `crate::rsync::apply_with` instead of
+// being built up as a complete `Vec` first; every other algorithm, and
+// `ApplyMode::LessTime`, fall back to `apply` plus one `write_all`.
+fn write_patched_entry(
+    new_archive: &mut zip::ZipWriter<std::fs::File>,
+    original_contents: &[u8],
+    patch: &Patch,
+    mode: ApplyMode,
+) -> Result<(), Error> {
+    if patch.diff_algorithm == DiffAlgorithm::Rsync020 {
+        crate::rsync::apply_with(original_contents, patch, new_archive, mode)
+    } else {
+        let contents = apply(original_contents, patch)?;
+        new_archive
+            .write_all(&contents)
+            .map_err(|e| Error::IoError(e.to_string()))
+    }
+}
+
+fn apply_operation(
+    base_archive: &mut zip::ZipArchive<std::io::Cursor<Vec<u8>>>,
+    new_archive: &mut zip::ZipWriter<std::fs::File>,
+    path: &str,
+    operation: Operation,
+    metadata: &std::collections::HashMap<String, FileMetadata>,
+    chunk_store: &std::collections::HashMap<String, Vec<u8>>,
+    fsst_table: Option<&crate::fsst::SymbolTable>,
+    password_base: Option<&str>,
+    password_after: Option<&str>,
+    mode: ApplyMode,
+    directories_to_create: &mut std::collections::HashSet<String>,
+) -> Result<(), Error> {
+    match operation {
+        Operation::Patch(patch) => {
+            debug!("Applying patch to file: {}", path);
+            // Read original file
+            let mut base_file = open_entry(base_archive, path, password_base)?
+                .ok_or_else(|| Error::ZipError(format!("entry {} not found", path)))?;
+            let mut original_contents = Vec::new();
+            base_file
+                .read_to_end(&mut original_contents)
+                .map_err(|e| Error::IoError(e.to_string()))?;
+
+            // Write new file, replaying its captured metadata
+            let options = file_options_for(path, metadata, password_after);
+            start_entry(new_archive, path, options, metadata)?;
+            write_patched_entry(new_archive, &original_contents, &patch, mode)?;
+            directories_to_create.extend(get_directories_of_file(path));
+        }
+        Operation::PutFile {
+            compress_algorithm,
+            data,
+        } => {
+            debug!("Adding new file: {}", path);
+            let contents = if compress_algorithm == CompressAlgorithm::Fsst {
+                let table = fsst_table.ok_or_else(|| {
+                    Error::FsstError(format!(
+                        "entry {} is FSST-compressed but the patch set carries no symbol table",
+                        path
+                    ))
+                })?;
+                crate::fsst::decode(table, &data)?
+            } else {
+                compress_algorithm.decompress(&data)?
+            };
+            // Write new file directly, replaying its captured metadata
+            let options = file_options_for(path, metadata, password_after);
+            start_entry(new_archive, path, options, metadata)?;
+            new_archive
+                .write_all(&contents)
+                .map_err(|e| Error::IoError(e.to_string()))?;
+            directories_to_create.extend(get_directories_of_file(path));
+        }
+        Operation::DeleteFile => {
+            debug!("Deleting file: {}", path);
+            // Skip this file - don't copy it to new archive
+        }
+        Operation::FileStaysSame => {
+            debug!("File stays same, raw-copying: {}", path);
+            // Stream the already-compressed local-file data and
+            // central-directory record straight into the new archive
+            // instead of decompressing and re-encoding as Stored. This
+            // both avoids the recompress round-trip and keeps the
+            // entry's original compression method, mtime, and unix
+            // mode intact automatically.
+            let base_file = base_archive
+                .by_name(path)
+                .map_err(|e| Error::ZipError(e.to_string()))?;
+            new_archive
+                .raw_copy_file(base_file)
+                .map_err(|e| Error::ZipError(e.to_string()))?;
+            directories_to_create.extend(get_directories_of_file(path));
+        }
+        Operation::MetadataOnly(_) => {
+            debug!("Replaying metadata only for: {}", path);
+            // Bytes are unchanged but the mode/mtime changed, so a raw
+            // copy would carry over the stale metadata: decode once and
+            // rewrite with the new metadata instead of a full diff.
+            let mut contents = Vec::new();
+            open_entry(base_archive, path, password_base)?
+                .ok_or_else(|| Error::ZipError(format!("entry {} not found", path)))?
+                .read_to_end(&mut contents)
+                .map_err(|e| Error::IoError(e.to_string()))?;
+
+            let options = file_options_for(path, metadata, password_after);
+            start_entry(new_archive, path, options, metadata)?;
+            new_archive
+                .write_all(&contents)
+                .map_err(|e| Error::IoError(e.to_string()))?;
+            directories_to_create.extend(get_directories_of_file(path));
+        }
+        Operation::CopyFrom(source) => {
+            debug!("Copying {} from existing entry {}", path, source);
+            // The target's content is byte-identical to `source` in the
+            // base archive: raw-copy its compressed bytes under the new
+            // name instead of re-adding the full content.
+            let source_file = base_archive
+                .by_name(&source)
+                .map_err(|e| Error::ZipError(e.to_string()))?;
+            new_archive
+                .raw_copy_file_rename(source_file, path)
+                .map_err(|e| Error::ZipError(e.to_string()))?;
+            directories_to_create.extend(get_directories_of_file(path));
+        }
+        Operation::MoveFile { from, patch } => {
+            debug!("Moving {} to {}", from, path);
+            let mut source_contents = Vec::new();
+            open_entry(base_archive, &from, password_base)?
+                .ok_or_else(|| Error::ZipError(format!("entry {} not found", from)))?
+                .read_to_end(&mut source_contents)
+                .map_err(|e| Error::IoError(e.to_string()))?;
+
+            let options = file_options_for(path, metadata, password_after);
+            match patch {
+                Some(patch) => {
+                    start_entry(new_archive, path, options, metadata)?;
+                    write_patched_entry(new_archive, &source_contents, &patch, mode)?;
+                }
+                None => {
+                    start_entry(new_archive, path, options, metadata)?;
+                    new_archive
+                        .write_all(&source_contents)
+                        .map_err(|e| Error::IoError(e.to_string()))?;
+                }
+            }
+            directories_to_create.extend(get_directories_of_file(path));
+        }
+        Operation::DeltaFrom { source, patch } => {
+            debug!("Applying delta to {} against existing entry {}", path, source);
+            let mut source_contents = Vec::new();
+            open_entry(base_archive, &source, password_base)?
+                .ok_or_else(|| Error::ZipError(format!("entry {} not found", source)))?
+                .read_to_end(&mut source_contents)
+                .map_err(|e| Error::IoError(e.to_string()))?;
+
+            let options = file_options_for(path, metadata, password_after);
+            start_entry(new_archive, path, options, metadata)?;
+            write_patched_entry(new_archive, &source_contents, &patch, mode)?;
+            directories_to_create.extend(get_directories_of_file(path));
+        }
+        Operation::Chunked(hashes) => {
+            debug!("Reassembling {} from {} chunks", path, hashes.len());
+            let mut contents = Vec::new();
+            for content_hash in &hashes {
+                let chunk = chunk_store
+                    .get(content_hash)
+                    .ok_or_else(|| Error::MissingChunk(content_hash.clone()))?;
+                contents.extend_from_slice(chunk);
+            }
+
+            let options = file_options_for(path, metadata, password_after);
+            start_entry(new_archive, path, options, metadata)?;
+            new_archive
+                .write_all(&contents)
+                .map_err(|e| Error::IoError(e.to_string()))?;
+            directories_to_create.extend(get_directories_of_file(path));
+        }
+    }
+
+    Ok(())
+}
+
+// Creates the directories any written entry needed and finalizes the
+// archive. Shared by `apply_zip_impl` and `apply_zip_streaming`.
+fn finish_archive(
+    mut new_archive: zip::ZipWriter<std::fs::File>,
+    directories_to_create: std::collections::HashSet<String>,
+    path_after: &str,
+) -> Result<(), Error> {
+    let directory_options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    for dir in directories_to_create {
+        trace!("creating directory {}", dir);
+
+        new_archive
+            .add_directory(dir, directory_options)
+            .map_err(|e| Error::ZipError(e.to_string()))?;
+    }
+
+    // Finalize the ZIP file
+    new_archive
+        .finish()
+        .map_err(|e| Error::ZipError(e.to_string()))?;
+
+    info!("Successfully created patched archive: {}", path_after);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::patch::Patch;
+
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+    use std::io::{Read, Write};
+    use tempfile::TempDir;
+
+    use std::sync::Once;
 
     static INIT: Once = Once::new();
 
@@ -374,91 +1549,597 @@ mod tests {
         });
     }
 
-    fn create_test_zip(files: &[(&str, Vec<u8>)]) -> Result<Vec<u8>, Error> {
-        let cursor = std::io::Cursor::new(Vec::new());
-        let mut zip = zip::ZipWriter::new(cursor);
-        let options =
-            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    fn create_test_zip(files: &[(&str, Vec<u8>)]) -> Result<Vec<u8>, Error> {
+        let cursor = std::io::Cursor::new(Vec::new());
+        let mut zip = zip::ZipWriter::new(cursor);
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+
+        for (name, contents) in files {
+            if *name == "" {
+                continue;
+            }
+            zip.start_file(*name, options)
+                .map_err(|e| Error::ZipError(e.to_string()))?;
+            zip.write_all(contents)
+                .map_err(|e| Error::IoError(e.to_string()))?;
+        }
+
+        Ok(zip
+            .finish()
+            .map_err(|e| Error::ZipError(e.to_string()))?
+            .into_inner())
+    }
+
+    // Builds the metadata map expected for entries written with
+    // `create_test_zip`'s default options (Stored, default mtime, no unix
+    // mode).
+    fn expected_metadata(paths: &[&str]) -> std::collections::HashMap<String, FileMetadata> {
+        let default_modified = zip::DateTime::default();
+        paths
+            .iter()
+            .map(|path| {
+                (
+                    path.to_string(),
+                    FileMetadata {
+                        last_modified: (
+                            default_modified.datepart(),
+                            default_modified.timepart(),
+                        ),
+                        unix_mode: None,
+                        compression_method: zip::CompressionMethod::Stored.serialize_to_u16(),
+                        encrypted: false,
+                        extra_field: Vec::new(),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_and_apply_basic() -> Result<(), Error> {
+        setup_logger();
+
+        let temp_dir = TempDir::new().map_err(|e| Error::IoError(e.to_string()))?;
+
+        // Create before.zip with a single file
+        let before_zip = create_test_zip(&[("test.txt", b"Hello World".into())])?;
+        let before_path = temp_dir.path().join("before.zip");
+        fs::write(&before_path, before_zip).map_err(|e| Error::IoError(e.to_string()))?;
+
+        // Create after.zip with modified content
+        let after_zip = create_test_zip(&[("test.txt", b"Hello Modified World".into())])?;
+        let after_path = temp_dir.path().join("after.zip");
+        fs::write(&after_path, after_zip).map_err(|e| Error::IoError(e.to_string()))?;
+
+        // Generate diff
+        let patch_set = diff_zip(
+            before_path.to_string_lossy().to_string(),
+            after_path.to_string_lossy().to_string(),
+            DiffAlgorithm::Bidiff1,
+            CompressAlgorithm::None,
+        )?;
+
+        assert_eq!(patch_set.operations.0.len(), 1);
+        assert_eq!(
+            patch_set.operations.0[0].1,
+            Operation::Patch(Patch {
+                diff_algorithm: DiffAlgorithm::Bidiff1,
+                compress_algorithm: CompressAlgorithm::None,
+                hash_algorithm: HashAlgorithm::Md5,
+                before_hash: "b10a8db164e0754105b7a99be72e3fe5".to_string(),
+                after_hash: "77a55ec2b0808d5a1ef1173fcfce9763".to_string(),
+                patch: vec![
+                    223, 177, 0, 0, 0, 16, 0, 0, 6, 0, 0, 0, 0, 0, 0, 14, 77, 111, 100, 105, 102,
+                    105, 101, 100, 32, 87, 111, 114, 108, 100, 0,
+                ],
+                block_size: None,
+                encrypted: false,
+            })
+        );
+
+        // Create output path for patched zip
+        let output_path = temp_dir.path().join("output.zip");
+
+        // Apply patch
+        apply_zip(
+            &before_path.to_string_lossy(),
+            patch_set,
+            output_path.to_string_lossy().to_string(),
+        )?;
+
+        // Verify the contents
+        let mut output_archive = zip::ZipArchive::new(std::io::Cursor::new(
+            fs::read(&output_path).map_err(|e| Error::IoError(e.to_string()))?,
+        ))
+        .map_err(|e| Error::ZipError(e.to_string()))?;
+
+        let mut file = output_archive
+            .by_name("test.txt")
+            .map_err(|e| Error::ZipError(e.to_string()))?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)
+            .map_err(|e| Error::IoError(e.to_string()))?;
+
+        assert_eq!(contents, b"Hello Modified World");
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_and_apply_with_cdc() -> Result<(), Error> {
+        setup_logger();
+
+        let temp_dir = TempDir::new().map_err(|e| Error::IoError(e.to_string()))?;
+
+        // A payload comfortably larger than MIN_CHUNK_SIZE so it's actually
+        // split into multiple chunks, with a shared prefix/suffix around an
+        // edited middle section.
+        let prefix = vec![b'a'; 6 * 1024];
+        let suffix = vec![b'b'; 6 * 1024];
+
+        let mut before_contents = prefix.clone();
+        before_contents.extend(b"before middle section");
+        before_contents.extend(suffix.clone());
+
+        let mut after_contents = prefix.clone();
+        after_contents.extend(b"after middle section, a bit longer");
+        after_contents.extend(suffix.clone());
+
+        let before_zip = create_test_zip(&[
+            ("big.bin", before_contents.clone()),
+            ("unrelated.txt", prefix.clone()),
+        ])?;
+        let before_hash = hash(&before_zip);
+        let before_path = temp_dir.path().join("before.zip");
+        fs::write(&before_path, before_zip).map_err(|e| Error::IoError(e.to_string()))?;
+
+        let after_zip = create_test_zip(&[
+            ("big.bin", after_contents.clone()),
+            ("unrelated.txt", prefix.clone()),
+        ])?;
+        let after_path = temp_dir.path().join("after.zip");
+        fs::write(&after_path, after_zip).map_err(|e| Error::IoError(e.to_string()))?;
+
+        let patch_set = diff_zip(
+            before_path.to_string_lossy().to_string(),
+            after_path.to_string_lossy().to_string(),
+            DiffAlgorithm::Cdc,
+            CompressAlgorithm::None,
+        )?;
+        assert_eq!(patch_set.hash_before, before_hash);
+
+        let chunked = patch_set
+            .operations
+            .0
+            .iter()
+            .find(|(path, _)| path == "big.bin")
+            .map(|(_, op)| op);
+        let hashes = match chunked {
+            Some(Operation::Chunked(hashes)) => hashes,
+            other => panic!("expected a Chunked operation for big.bin, got {:?}", other),
+        };
+        assert!(!hashes.is_empty());
+        for content_hash in hashes {
+            assert!(patch_set.chunk_store.contains_key(content_hash));
+        }
+
+        let output_path = temp_dir.path().join("output.zip");
+        apply_zip(
+            &before_path.to_string_lossy(),
+            patch_set,
+            output_path.to_string_lossy().to_string(),
+        )?;
+
+        let mut output_archive = zip::ZipArchive::new(std::io::Cursor::new(
+            fs::read(&output_path).map_err(|e| Error::IoError(e.to_string()))?,
+        ))
+        .map_err(|e| Error::ZipError(e.to_string()))?;
+
+        let mut contents = Vec::new();
+        output_archive
+            .by_name("big.bin")
+            .map_err(|e| Error::ZipError(e.to_string()))?
+            .read_to_end(&mut contents)
+            .map_err(|e| Error::IoError(e.to_string()))?;
+
+        assert_eq!(contents, after_contents);
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_and_apply_with_change_past_partial_hash_boundary() -> Result<(), Error> {
+        setup_logger();
+
+        let temp_dir = TempDir::new().map_err(|e| Error::IoError(e.to_string()))?;
+
+        // Larger than `PARTIAL_HASH_BYTES`, with identical first blocks and a
+        // change only past the boundary, so `compare_entry`'s partial-hash
+        // pre-check can't short-circuit on the prefix alone and must fall
+        // back to a full hash (and, since that differs, a full diff).
+        let shared_prefix = vec![b'a'; PARTIAL_HASH_BYTES + 1024];
+        let mut before_contents = shared_prefix.clone();
+        before_contents.extend(b"before tail");
+        let mut after_contents = shared_prefix.clone();
+        after_contents.extend(b"after tail, a bit longer");
+
+        let before_zip = create_test_zip(&[
+            ("changed.bin", before_contents.clone()),
+            ("unchanged.bin", shared_prefix.clone()),
+        ])?;
+        let before_path = temp_dir.path().join("before.zip");
+        fs::write(&before_path, before_zip).map_err(|e| Error::IoError(e.to_string()))?;
+
+        let after_zip = create_test_zip(&[
+            ("changed.bin", after_contents.clone()),
+            ("unchanged.bin", shared_prefix.clone()),
+        ])?;
+        let after_path = temp_dir.path().join("after.zip");
+        fs::write(&after_path, after_zip).map_err(|e| Error::IoError(e.to_string()))?;
+
+        let patch_set = diff_zip(
+            before_path.to_string_lossy().to_string(),
+            after_path.to_string_lossy().to_string(),
+            DiffAlgorithm::Bidiff1,
+            CompressAlgorithm::None,
+        )?;
+
+        let changed_op = patch_set
+            .operations
+            .0
+            .iter()
+            .find(|(path, _)| path == "changed.bin")
+            .map(|(_, op)| op);
+        assert!(matches!(changed_op, Some(Operation::Patch(_))));
+
+        let unchanged_op = patch_set
+            .operations
+            .0
+            .iter()
+            .find(|(path, _)| path == "unchanged.bin")
+            .map(|(_, op)| op);
+        assert_eq!(unchanged_op, Some(&Operation::FileStaysSame));
+
+        let output_path = temp_dir.path().join("output.zip");
+        apply_zip(
+            &before_path.to_string_lossy(),
+            patch_set,
+            output_path.to_string_lossy().to_string(),
+        )?;
+
+        let mut output_archive = zip::ZipArchive::new(std::io::Cursor::new(
+            fs::read(&output_path).map_err(|e| Error::IoError(e.to_string()))?,
+        ))
+        .map_err(|e| Error::ZipError(e.to_string()))?;
+
+        let mut contents = Vec::new();
+        output_archive
+            .by_name("changed.bin")
+            .map_err(|e| Error::ZipError(e.to_string()))?
+            .read_to_end(&mut contents)
+            .map_err(|e| Error::IoError(e.to_string()))?;
+        assert_eq!(contents, after_contents);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_and_apply_with_lz4_and_brotli_compression() -> Result<(), Error> {
+        setup_logger();
+
+        let temp_dir = TempDir::new().map_err(|e| Error::IoError(e.to_string()))?;
+
+        let before_zip = create_test_zip(&[("file.txt", b"Original content repeated repeated repeated".to_vec())])?;
+        let before_path = temp_dir.path().join("before.zip");
+        fs::write(&before_path, before_zip).map_err(|e| Error::IoError(e.to_string()))?;
+
+        let new_file_contents = b"Brand new file content repeated repeated repeated".to_vec();
+        let after_zip = create_test_zip(&[
+            (
+                "file.txt",
+                b"Modified content repeated repeated repeated".to_vec(),
+            ),
+            ("new.txt", new_file_contents.clone()),
+        ])?;
+        let after_path = temp_dir.path().join("after.zip");
+        fs::write(&after_path, after_zip).map_err(|e| Error::IoError(e.to_string()))?;
+
+        for compress_algorithm in [CompressAlgorithm::Lz4, CompressAlgorithm::Brotli] {
+            let patch_set = diff_zip(
+                before_path.to_string_lossy().to_string(),
+                after_path.to_string_lossy().to_string(),
+                DiffAlgorithm::Bidiff1,
+                compress_algorithm,
+            )?;
+
+            let put_file = patch_set
+                .operations
+                .0
+                .iter()
+                .find(|(path, _)| path == "new.txt")
+                .map(|(_, op)| op);
+            match put_file {
+                Some(Operation::PutFile {
+                    compress_algorithm: op_algorithm,
+                    ..
+                }) => assert_eq!(*op_algorithm, compress_algorithm),
+                other => panic!("expected a PutFile operation for new.txt, got {:?}", other),
+            }
+
+            let output_path = temp_dir.path().join(format!("output_{:?}.zip", compress_algorithm));
+            apply_zip(
+                &before_path.to_string_lossy(),
+                patch_set,
+                output_path.to_string_lossy().to_string(),
+            )?;
+
+            let mut output_archive = zip::ZipArchive::new(std::io::Cursor::new(
+                fs::read(&output_path).map_err(|e| Error::IoError(e.to_string()))?,
+            ))
+            .map_err(|e| Error::ZipError(e.to_string()))?;
+
+            let mut contents = Vec::new();
+            output_archive
+                .by_name("new.txt")
+                .map_err(|e| Error::ZipError(e.to_string()))?
+                .read_to_end(&mut contents)
+                .map_err(|e| Error::IoError(e.to_string()))?;
+            assert_eq!(contents, new_file_contents);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_and_apply_detects_renamed_and_edited_file() -> Result<(), Error> {
+        setup_logger();
+
+        let temp_dir = TempDir::new().map_err(|e| Error::IoError(e.to_string()))?;
+
+        // Large and varied enough to split into several CDC chunks, so that
+        // editing only the tail still leaves most chunks shared between the
+        // old and new path - chunk boundaries only depend on preceding
+        // bytes, so everything before the edit cuts identically either way.
+        let shared_body: Vec<u8> = (0..40_000u32).map(|i| (i % 251) as u8).collect();
+
+        let mut before_contents = shared_body.clone();
+        before_contents.extend_from_slice(b"original tail");
+
+        let mut after_contents = shared_body;
+        after_contents.extend_from_slice(b"edited tail, with new content appended at the end");
+
+        let before_zip = create_test_zip(&[("old/report.bin", before_contents.clone())])?;
+        let before_path = temp_dir.path().join("before.zip");
+        fs::write(&before_path, before_zip).map_err(|e| Error::IoError(e.to_string()))?;
+
+        let after_zip = create_test_zip(&[("new/report.bin", after_contents.clone())])?;
+        let after_path = temp_dir.path().join("after.zip");
+        fs::write(&after_path, after_zip).map_err(|e| Error::IoError(e.to_string()))?;
+
+        let patch_set = diff_zip(
+            before_path.to_string_lossy().to_string(),
+            after_path.to_string_lossy().to_string(),
+            DiffAlgorithm::Bidiff1,
+            CompressAlgorithm::None,
+        )?;
+
+        let renamed_op = patch_set
+            .operations
+            .0
+            .iter()
+            .find(|(path, _)| path == "new/report.bin")
+            .map(|(_, op)| op);
+        match renamed_op {
+            Some(Operation::MoveFile { from, patch }) => {
+                assert_eq!(from, "old/report.bin");
+                assert!(patch.is_some());
+            }
+            other => panic!(
+                "expected a MoveFile operation for new/report.bin, got {:?}",
+                other
+            ),
+        }
+
+        // The old path's deletion should have been folded into the move
+        // rather than also appearing as a standalone `DeleteFile`.
+        assert!(
+            !patch_set
+                .operations
+                .0
+                .iter()
+                .any(|(path, _)| path == "old/report.bin"),
+            "old path should not appear once folded into MoveFile"
+        );
+
+        let output_path = temp_dir.path().join("output.zip");
+        apply_zip(
+            &before_path.to_string_lossy(),
+            patch_set,
+            output_path.to_string_lossy().to_string(),
+        )?;
+
+        let mut output_archive = zip::ZipArchive::new(std::io::Cursor::new(
+            fs::read(&output_path).map_err(|e| Error::IoError(e.to_string()))?,
+        ))
+        .map_err(|e| Error::ZipError(e.to_string()))?;
+
+        assert!(output_archive.by_name("old/report.bin").is_err());
+        let mut contents = Vec::new();
+        output_archive
+            .by_name("new/report.bin")
+            .map_err(|e| Error::ZipError(e.to_string()))?
+            .read_to_end(&mut contents)
+            .map_err(|e| Error::IoError(e.to_string()))?;
+        assert_eq!(contents, after_contents);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_and_apply_detects_duplicate_content_under_new_path() -> Result<(), Error> {
+        setup_logger();
+
+        let temp_dir = TempDir::new().map_err(|e| Error::IoError(e.to_string()))?;
+
+        // Large and varied enough to split into several CDC chunks, so that
+        // a new file sharing most of the body but with a different tail
+        // still overlaps enough chunks with `report.bin` to count as similar.
+        let shared_body: Vec<u8> = (0..40_000u32).map(|i| (i % 251) as u8).collect();
+
+        let mut original_contents = shared_body.clone();
+        original_contents.extend_from_slice(b"original tail");
+
+        let mut copy_contents = shared_body;
+        copy_contents.extend_from_slice(b"edited tail, with new content appended at the end");
+
+        // `report.bin` is kept unchanged in `after`, so the new `copy.bin`
+        // can't be folded into a rename - it must be delta-encoded against
+        // the still-present `report.bin` instead.
+        let before_zip = create_test_zip(&[("report.bin", original_contents.clone())])?;
+        let before_path = temp_dir.path().join("before.zip");
+        fs::write(&before_path, before_zip).map_err(|e| Error::IoError(e.to_string()))?;
+
+        let after_zip = create_test_zip(&[
+            ("report.bin", original_contents.clone()),
+            ("copy.bin", copy_contents.clone()),
+        ])?;
+        let after_path = temp_dir.path().join("after.zip");
+        fs::write(&after_path, after_zip).map_err(|e| Error::IoError(e.to_string()))?;
 
-        for (name, contents) in files {
-            if *name == "" {
-                continue;
+        let patch_set = diff_zip(
+            before_path.to_string_lossy().to_string(),
+            after_path.to_string_lossy().to_string(),
+            DiffAlgorithm::Bidiff1,
+            CompressAlgorithm::None,
+        )?;
+
+        let copy_op = patch_set
+            .operations
+            .0
+            .iter()
+            .find(|(path, _)| path == "copy.bin")
+            .map(|(_, op)| op);
+        match copy_op {
+            Some(Operation::DeltaFrom { source, .. }) => {
+                assert_eq!(source, "report.bin");
             }
-            zip.start_file(*name, options)
-                .map_err(|e| Error::ZipError(e.to_string()))?;
-            zip.write_all(contents)
-                .map_err(|e| Error::IoError(e.to_string()))?;
+            other => panic!("expected a DeltaFrom operation for copy.bin, got {:?}", other),
         }
 
-        Ok(zip
-            .finish()
+        let output_path = temp_dir.path().join("output.zip");
+        apply_zip(
+            &before_path.to_string_lossy(),
+            patch_set,
+            output_path.to_string_lossy().to_string(),
+        )?;
+
+        let mut output_archive = zip::ZipArchive::new(std::io::Cursor::new(
+            fs::read(&output_path).map_err(|e| Error::IoError(e.to_string()))?,
+        ))
+        .map_err(|e| Error::ZipError(e.to_string()))?;
+
+        let mut report_contents = Vec::new();
+        output_archive
+            .by_name("report.bin")
             .map_err(|e| Error::ZipError(e.to_string()))?
-            .into_inner())
+            .read_to_end(&mut report_contents)
+            .map_err(|e| Error::IoError(e.to_string()))?;
+        assert_eq!(report_contents, original_contents);
+
+        let mut copy_result_contents = Vec::new();
+        output_archive
+            .by_name("copy.bin")
+            .map_err(|e| Error::ZipError(e.to_string()))?
+            .read_to_end(&mut copy_result_contents)
+            .map_err(|e| Error::IoError(e.to_string()))?;
+        assert_eq!(copy_result_contents, copy_contents);
+
+        Ok(())
     }
 
     #[test]
-    fn test_diff_and_apply_basic() -> Result<(), Error> {
+    fn test_diff_and_apply_trains_fsst_dictionary_for_many_small_files() -> Result<(), Error> {
         setup_logger();
 
         let temp_dir = TempDir::new().map_err(|e| Error::IoError(e.to_string()))?;
 
-        // Create before.zip with a single file
-        let before_zip = create_test_zip(&[("test.txt", b"Hello World".into())])?;
+        let before_zip = create_test_zip(&[("readme.txt", b"nothing to see here".into())])?;
         let before_path = temp_dir.path().join("before.zip");
         fs::write(&before_path, before_zip).map_err(|e| Error::IoError(e.to_string()))?;
 
-        // Create after.zip with modified content
-        let after_zip = create_test_zip(&[("test.txt", b"Hello Modified World".into())])?;
+        // More than `FSST_MIN_CANDIDATE_ENTRIES` brand-new files, all small
+        // and sharing most of their vocabulary, so a shared symbol table has
+        // something to learn and beats each entry paying its own Zstd framing.
+        let entries: Vec<(String, Vec<u8>)> = (0..12)
+            .map(|i| {
+                (
+                    format!("records/item-{}.json", i),
+                    format!(r#"{{"id": {}, "name": "widget", "active": true}}"#, i).into_bytes(),
+                )
+            })
+            .chain(std::iter::once((
+                "readme.txt".to_string(),
+                b"nothing to see here".to_vec(),
+            )))
+            .collect();
+        let after_zip_entries: Vec<(&str, Vec<u8>)> = entries
+            .iter()
+            .map(|(path, contents)| (path.as_str(), contents.clone()))
+            .collect();
+        let after_zip = create_test_zip(&after_zip_entries)?;
         let after_path = temp_dir.path().join("after.zip");
         fs::write(&after_path, after_zip).map_err(|e| Error::IoError(e.to_string()))?;
 
-        // Generate diff
         let patch_set = diff_zip(
             before_path.to_string_lossy().to_string(),
             after_path.to_string_lossy().to_string(),
             DiffAlgorithm::Bidiff1,
-            CompressAlgorithm::None,
+            CompressAlgorithm::Zstd { level: 3 },
         )?;
 
-        assert_eq!(patch_set.operations.0.len(), 1);
-        assert_eq!(
-            patch_set.operations.0[0].1,
-            Operation::Patch(Patch {
-                diff_algorithm: DiffAlgorithm::Bidiff1,
-                compress_algorithm: CompressAlgorithm::None,
-                before_hash: "b10a8db164e0754105b7a99be72e3fe5".to_string(),
-                after_hash: "77a55ec2b0808d5a1ef1173fcfce9763".to_string(),
-                patch: vec![
-                    223, 177, 0, 0, 0, 16, 0, 0, 6, 0, 0, 0, 0, 0, 0, 14, 77, 111, 100, 105, 102,
-                    105, 101, 100, 32, 87, 111, 114, 108, 100, 0,
-                ],
-            })
+        assert!(
+            patch_set.fsst_table.is_some(),
+            "expected diff_zip to train an FSST dictionary over the new small files"
         );
 
-        // Create output path for patched zip
-        let output_path = temp_dir.path().join("output.zip");
+        for i in 0..12 {
+            let op = patch_set
+                .operations
+                .0
+                .iter()
+                .find(|(path, _)| path == &format!("records/item-{}.json", i))
+                .map(|(_, op)| op);
+            match op {
+                Some(Operation::PutFile {
+                    compress_algorithm, ..
+                }) => {
+                    assert_eq!(*compress_algorithm, CompressAlgorithm::Fsst);
+                }
+                other => panic!("expected an FSST-compressed PutFile, got {:?}", other),
+            }
+        }
 
-        // Apply patch
+        let output_path = temp_dir.path().join("output.zip");
         apply_zip(
             &before_path.to_string_lossy(),
             patch_set,
             output_path.to_string_lossy().to_string(),
         )?;
 
-        // Verify the contents
         let mut output_archive = zip::ZipArchive::new(std::io::Cursor::new(
             fs::read(&output_path).map_err(|e| Error::IoError(e.to_string()))?,
         ))
         .map_err(|e| Error::ZipError(e.to_string()))?;
 
-        let mut file = output_archive
-            .by_name("test.txt")
-            .map_err(|e| Error::ZipError(e.to_string()))?;
-        let mut contents = Vec::new();
-        file.read_to_end(&mut contents)
-            .map_err(|e| Error::IoError(e.to_string()))?;
+        for (path, expected_contents) in &entries {
+            let mut contents = Vec::new();
+            output_archive
+                .by_name(path)
+                .map_err(|e| Error::ZipError(e.to_string()))?
+                .read_to_end(&mut contents)
+                .map_err(|e| Error::IoError(e.to_string()))?;
+            assert_eq!(&contents, expected_contents);
+        }
 
-        assert_eq!(contents, b"Hello Modified World");
         Ok(())
     }
 
@@ -497,8 +2178,12 @@ mod tests {
                     ("file1.txt".to_string(), Operation::FileStaysSame),
                     ("file2.txt".to_string(), Operation::DeleteFile),
                 ]),
+                hash_algorithm: HashAlgorithm::Md5,
                 hash_before: before_hash,
                 operations_hash: "2a8a469ad35c75f628e7c1ebe37afbf0".to_string(),
+                metadata: expected_metadata(&["file1.txt"]),
+                chunk_store: std::collections::HashMap::new(),
+                fsst_table: None,
             }
         );
 
@@ -566,22 +2251,32 @@ mod tests {
                         Operation::Patch(Patch {
                             diff_algorithm: DiffAlgorithm::Bidiff1,
                             compress_algorithm: CompressAlgorithm::None,
+                            hash_algorithm: HashAlgorithm::Md5,
                             before_hash: "2f03b03637bf162937793f756f0f1583".to_string(),
                             after_hash: "15b8181404e3a6b2e046de781b702654".to_string(),
                             patch: vec![
                                 223, 177, 0, 0, 0, 16, 0, 0, 6, 0, 0, 0, 0, 0, 0, 9, 32, 77, 111,
                                 100, 105, 102, 105, 101, 100, 0,
                             ],
+                            block_size: None,
+                            encrypted: false,
                         }),
                     ),
                     ("dir2/".to_string(), Operation::DeleteFile),
                     (
                         "dir3/file3.txt".to_string(),
-                        Operation::PutFile(vec![70, 105, 108, 101, 32, 51]),
+                        Operation::PutFile {
+                            compress_algorithm: CompressAlgorithm::None,
+                            data: vec![70, 105, 108, 101, 32, 51],
+                        },
                     ),
                 ]),
+                hash_algorithm: HashAlgorithm::Md5,
                 hash_before: before_hash,
                 operations_hash: "c52153314592d31ddfda9bbf6390a991".to_string(),
+                metadata: expected_metadata(&["dir1/file1.txt", "dir3/file3.txt"]),
+                chunk_store: std::collections::HashMap::new(),
+                fsst_table: None,
             }
         );
 
@@ -622,6 +2317,184 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_diff_zip_with_thread_limit_matches_unbounded() -> Result<(), Error> {
+        setup_logger();
+
+        let temp_dir = TempDir::new().map_err(|e| Error::IoError(e.to_string()))?;
+
+        let before_zip = create_test_zip(&[
+            ("a.txt", b"file a original content repeated repeated".to_vec()),
+            ("b.txt", b"file b original content repeated repeated".to_vec()),
+            ("c.txt", b"file c original content repeated repeated".to_vec()),
+            ("d.txt", b"file d original content repeated repeated".to_vec()),
+        ])?;
+        let before_path = temp_dir.path().join("before.zip");
+        fs::write(&before_path, before_zip).map_err(|e| Error::IoError(e.to_string()))?;
+
+        let after_zip = create_test_zip(&[
+            ("a.txt", b"file a MODIFIED content repeated repeated".to_vec()),
+            ("b.txt", b"file b MODIFIED content repeated repeated".to_vec()),
+            ("c.txt", b"file c MODIFIED content repeated repeated".to_vec()),
+            ("d.txt", b"file d MODIFIED content repeated repeated".to_vec()),
+        ])?;
+        let after_path = temp_dir.path().join("after.zip");
+        fs::write(&after_path, after_zip).map_err(|e| Error::IoError(e.to_string()))?;
+
+        let unbounded = diff_zip(
+            before_path.to_string_lossy().to_string(),
+            after_path.to_string_lossy().to_string(),
+            DiffAlgorithm::Bidiff1,
+            CompressAlgorithm::None,
+        )?;
+
+        // `operations_hash`/ordering must be identical regardless of how
+        // many threads (if any) the per-file diff pass used.
+        for max_threads in [Some(1), Some(2), None] {
+            let capped = diff_zip_with_thread_limit(
+                before_path.to_string_lossy().to_string(),
+                after_path.to_string_lossy().to_string(),
+                DiffAlgorithm::Bidiff1,
+                CompressAlgorithm::None,
+                max_threads,
+            )?;
+            assert_eq!(capped, unbounded);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_patch_set_container_roundtrip_and_streaming_apply() -> Result<(), Error> {
+        setup_logger();
+
+        let temp_dir = TempDir::new().map_err(|e| Error::IoError(e.to_string()))?;
+
+        let before_zip = create_test_zip(&[
+            ("a.txt", b"file a original content repeated repeated".to_vec()),
+            ("b.txt", b"file b original content repeated repeated".to_vec()),
+        ])?;
+        let before_path = temp_dir.path().join("before.zip");
+        fs::write(&before_path, before_zip).map_err(|e| Error::IoError(e.to_string()))?;
+
+        let after_zip = create_test_zip(&[
+            ("a.txt", b"file a MODIFIED content repeated repeated".to_vec()),
+            ("b.txt", b"file b original content repeated repeated".to_vec()),
+            ("c.txt", b"brand new file".to_vec()),
+        ])?;
+        let after_path = temp_dir.path().join("after.zip");
+        fs::write(&after_path, after_zip).map_err(|e| Error::IoError(e.to_string()))?;
+
+        let patch_set = diff_zip(
+            before_path.to_string_lossy().to_string(),
+            after_path.to_string_lossy().to_string(),
+            DiffAlgorithm::Bidiff1,
+            CompressAlgorithm::Zstd { level: 21 },
+        )?;
+
+        // Round-trip through the container format preserves the patch set
+        // exactly.
+        let mut container_bytes = Vec::new();
+        patch_set.write_to(&mut container_bytes)?;
+        let read_back = PatchSet::read_from(&mut container_bytes.as_slice())?;
+        assert_eq!(read_back, patch_set);
+
+        // Applying normally and applying from a streamed container produce
+        // byte-identical archives.
+        let applied_path = temp_dir.path().join("applied.zip");
+        apply_zip(
+            before_path.to_string_lossy().as_ref(),
+            patch_set,
+            applied_path.to_string_lossy().to_string(),
+        )?;
+
+        let streamed_path = temp_dir.path().join("streamed.zip");
+        apply_zip_streaming(
+            before_path.to_string_lossy().as_ref(),
+            &mut container_bytes.as_slice(),
+            streamed_path.to_string_lossy().to_string(),
+            None,
+            None,
+            ApplyMode::LessTime,
+        )?;
+
+        let applied_bytes = fs::read(&applied_path).map_err(|e| Error::IoError(e.to_string()))?;
+        let streamed_bytes =
+            fs::read(&streamed_path).map_err(|e| Error::IoError(e.to_string()))?;
+        assert_eq!(streamed_bytes, applied_bytes);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_zip_with_encrypted_patch() -> Result<(), Error> {
+        setup_logger();
+
+        let temp_dir = TempDir::new().map_err(|e| Error::IoError(e.to_string()))?;
+
+        let before_zip = create_test_zip(&[(
+            "a.txt",
+            b"file a original content repeated repeated".to_vec(),
+        )])?;
+        let before_path = temp_dir.path().join("before.zip");
+        fs::write(&before_path, before_zip).map_err(|e| Error::IoError(e.to_string()))?;
+
+        let after_zip = create_test_zip(&[(
+            "a.txt",
+            b"file a MODIFIED content repeated repeated".to_vec(),
+        )])?;
+        let after_path = temp_dir.path().join("after.zip");
+        fs::write(&after_path, after_zip).map_err(|e| Error::IoError(e.to_string()))?;
+
+        let patch_set = diff_zip(
+            before_path.to_string_lossy().to_string(),
+            after_path.to_string_lossy().to_string(),
+            DiffAlgorithm::Bidiff1,
+            CompressAlgorithm::Zstd { level: 21 },
+        )?;
+
+        let expected_applied_path = temp_dir.path().join("expected.zip");
+        apply_zip(
+            before_path.to_string_lossy().as_ref(),
+            PatchSet::from_bytes(&patch_set.to_bytes()?)?,
+            expected_applied_path.to_string_lossy().to_string(),
+        )?;
+
+        for encryption in [
+            crate::encryption::Encryption::Aes256Gcm,
+            crate::encryption::Encryption::ChaCha20Poly1305,
+        ] {
+            let encrypted = patch_set.encrypt("correct horse battery staple", encryption)?;
+
+            // A wrong passphrase must be rejected, not silently produce
+            // garbage output.
+            let wrong_passphrase_result =
+                PatchSet::decrypt(&encrypted, "wrong passphrase");
+            assert!(matches!(
+                wrong_passphrase_result,
+                Err(Error::AuthenticationFailed)
+            ));
+
+            let applied_path = temp_dir.path().join(format!("applied_{}.zip", encryption));
+            apply_zip_with_encrypted_patch(
+                before_path.to_string_lossy().as_ref(),
+                &encrypted,
+                applied_path.to_string_lossy().to_string(),
+                "correct horse battery staple",
+                None,
+                None,
+            )?;
+
+            let expected_bytes =
+                fs::read(&expected_applied_path).map_err(|e| Error::IoError(e.to_string()))?;
+            let applied_bytes =
+                fs::read(&applied_path).map_err(|e| Error::IoError(e.to_string()))?;
+            assert_eq!(applied_bytes, expected_bytes);
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_complex_roundtrip_diff_and_apply() -> Result<(), Error> {
         setup_logger();
@@ -696,69 +2569,97 @@ mod tests {
         assert_eq!(
             patch_v1_to_v2,
             PatchSet {
+                // Operations are sorted by path so `operations_hash` is
+                // deterministic regardless of how the per-file diffs were
+                // scheduled (see `process_directory`).
                 operations: Operations(vec![
-                    // Root directory changes
                     (
-                        "root1.txt".to_string(),
-                        Operation::Patch(Patch {
-                            diff_algorithm: DiffAlgorithm::Bidiff1,
+                        "parent1/child1/deep1.txt".to_string(),
+                        Operation::FileStaysSame
+                    ),
+                    (
+                        "parent1/child1/deep2.txt".to_string(),
+                        Operation::DeleteFile
+                    ),
+                    (
+                        "parent1/child1/deep3.txt".to_string(),
+                        Operation::PutFile {
                             compress_algorithm: CompressAlgorithm::None,
-                            before_hash: "f675e8894edcf33ae7097dcc4bfb89f9".to_string(),
-                            after_hash: "3468f9d6535a07b35c8acb8aa6aac781".to_string(),
-                            patch: vec![
-                                223, 177, 0, 0, 0, 16, 0, 0, 11, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                                9, 32, 109, 111, 100, 105, 102, 105, 101, 100, 0,
-                            ],
-                        })
+                            data: b"New deep file".to_vec(),
+                        }
                     ),
-                    ("root2.txt".to_string(), Operation::DeleteFile),
                     (
                         "parent1/file1.txt".to_string(),
                         Operation::Patch(Patch {
                             diff_algorithm: DiffAlgorithm::Bidiff1,
                             compress_algorithm: CompressAlgorithm::None,
+                            hash_algorithm: HashAlgorithm::Md5,
                             before_hash: "a138a74adecabef6294b55d2b28d3ea1".to_string(),
                             after_hash: "710d2bbb6df79b88d7b75bdefdcf28aa".to_string(),
                             patch: vec![
                                 223, 177, 0, 0, 0, 16, 0, 0, 13, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
                                 0, 0, 9, 32, 109, 111, 100, 105, 102, 105, 101, 100, 0,
                             ],
+                            block_size: None,
+                            encrypted: false,
                         })
                     ),
-                    (
-                        "parent1/child1/deep1.txt".to_string(),
-                        Operation::FileStaysSame
-                    ),
-                    (
-                        "parent1/child1/deep2.txt".to_string(),
-                        Operation::DeleteFile
-                    ),
-                    (
-                        "parent1/child1/deep3.txt".to_string(),
-                        Operation::PutFile(b"New deep file".to_vec())
-                    ),
-                    // parent2/ directory changes
-                    ("parent2/file2.txt".to_string(), Operation::FileStaysSame),
                     (
                         "parent2/child2/deep3.txt".to_string(),
                         Operation::Patch(Patch {
                             diff_algorithm: DiffAlgorithm::Bidiff1,
                             compress_algorithm: CompressAlgorithm::None,
+                            hash_algorithm: HashAlgorithm::Md5,
                             before_hash: "15bf70eee30b1805ab0e11510d30b41e".to_string(),
                             after_hash: "804237ac129569f027a2b55f8cf8d7db".to_string(),
                             patch: vec![
                                 223, 177, 0, 0, 0, 16, 0, 0, 11, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
                                 9, 32, 109, 111, 100, 105, 102, 105, 101, 100, 0,
                             ],
+                            block_size: None,
+                            encrypted: false,
                         })
                     ),
+                    ("parent2/file2.txt".to_string(), Operation::FileStaysSame),
                     (
                         "parent3/newfile.txt".to_string(),
-                        Operation::PutFile(b"Brand new file".to_vec())
+                        Operation::PutFile {
+                            compress_algorithm: CompressAlgorithm::None,
+                            data: b"Brand new file".to_vec(),
+                        }
+                    ),
+                    (
+                        "root1.txt".to_string(),
+                        Operation::Patch(Patch {
+                            diff_algorithm: DiffAlgorithm::Bidiff1,
+                            compress_algorithm: CompressAlgorithm::None,
+                            hash_algorithm: HashAlgorithm::Md5,
+                            before_hash: "f675e8894edcf33ae7097dcc4bfb89f9".to_string(),
+                            after_hash: "3468f9d6535a07b35c8acb8aa6aac781".to_string(),
+                            patch: vec![
+                                223, 177, 0, 0, 0, 16, 0, 0, 11, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                                9, 32, 109, 111, 100, 105, 102, 105, 101, 100, 0,
+                            ],
+                            block_size: None,
+                            encrypted: false,
+                        })
                     ),
+                    ("root2.txt".to_string(), Operation::DeleteFile),
                 ]),
+                hash_algorithm: HashAlgorithm::Md5,
                 hash_before: v1_hash,
                 operations_hash: "caf887830891091723fe5ada783f48b6".to_string(),
+                metadata: expected_metadata(&[
+                    "root1.txt",
+                    "parent1/file1.txt",
+                    "parent1/child1/deep1.txt",
+                    "parent1/child1/deep3.txt",
+                    "parent2/file2.txt",
+                    "parent2/child2/deep3.txt",
+                    "parent3/newfile.txt",
+                ]),
+                chunk_store: std::collections::HashMap::new(),
+                fsst_table: None,
             }
         );
 
@@ -785,21 +2686,10 @@ mod tests {
         assert_eq!(
             patch_v2_to_v3,
             PatchSet {
+                // Operations are sorted by path so `operations_hash` is
+                // deterministic regardless of how the per-file diffs were
+                // scheduled (see `process_directory`).
                 operations: Operations(vec![
-                    (
-                        "root1.txt".to_string(),
-                        Operation::Patch(Patch {
-                            diff_algorithm: DiffAlgorithm::Bidiff1,
-                            compress_algorithm: CompressAlgorithm::None,
-                            before_hash: "3468f9d6535a07b35c8acb8aa6aac781".to_string(),
-                            after_hash: "2ad3c7437786d6625776f0583bc3d6b2".to_string(),
-                            patch: vec![
-                                223, 177, 0, 0, 0, 16, 0, 0, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                                0, 0, 0, 0, 0, 0, 0, 0, 0, 6, 32, 97, 103, 97, 105, 110, 0
-                            ],
-                        })
-                    ),
-                    ("parent1/file1.txt".to_string(), Operation::FileStaysSame),
                     (
                         "parent1/child1/deep1.txt".to_string(),
                         Operation::DeleteFile
@@ -809,38 +2699,74 @@ mod tests {
                         Operation::Patch(Patch {
                             diff_algorithm: DiffAlgorithm::Bidiff1,
                             compress_algorithm: CompressAlgorithm::None,
+                            hash_algorithm: HashAlgorithm::Md5,
                             before_hash: "eb60615cbd4f6c8befc5dc7b387e77b9".to_string(),
                             after_hash: "ad96d84598d4994a819489d1762967e3".to_string(),
                             patch: vec![
                                 223, 177, 0, 0, 0, 16, 0, 0, 13, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
                                 0, 0, 9, 32, 109, 111, 100, 105, 102, 105, 101, 100, 0
-                            ]
+                            ],
+                            block_size: None,
+                            encrypted: false,
                         })
                     ),
+                    ("parent1/file1.txt".to_string(), Operation::FileStaysSame),
                     ("parent2/".to_string(), Operation::DeleteFile),
+                    (
+                        "parent3/another.txt".to_string(),
+                        Operation::PutFile {
+                            compress_algorithm: CompressAlgorithm::None,
+                            data: vec![
+                                65, 110, 111, 116, 104, 101, 114, 32, 110, 101, 119, 32, 102, 105,
+                                108, 101
+                            ],
+                        }
+                    ),
                     (
                         "parent3/newfile.txt".to_string(),
                         Operation::Patch(Patch {
                             diff_algorithm: DiffAlgorithm::Bidiff1,
                             compress_algorithm: CompressAlgorithm::None,
+                            hash_algorithm: HashAlgorithm::Md5,
                             before_hash: "98de949196bc048ff94069ea5e1c4446".to_string(),
                             after_hash: "0afd1f99b76a45e02719a43715c7071b".to_string(),
                             patch: vec![
                                 223, 177, 0, 0, 0, 16, 0, 0, 14, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
                                 0, 0, 0, 9, 32, 109, 111, 100, 105, 102, 105, 101, 100, 0
-                            ]
+                            ],
+                            block_size: None,
+                            encrypted: false,
                         })
                     ),
                     (
-                        "parent3/another.txt".to_string(),
-                        Operation::PutFile(vec![
-                            65, 110, 111, 116, 104, 101, 114, 32, 110, 101, 119, 32, 102, 105, 108,
-                            101
-                        ])
-                    )
+                        "root1.txt".to_string(),
+                        Operation::Patch(Patch {
+                            diff_algorithm: DiffAlgorithm::Bidiff1,
+                            compress_algorithm: CompressAlgorithm::None,
+                            hash_algorithm: HashAlgorithm::Md5,
+                            before_hash: "3468f9d6535a07b35c8acb8aa6aac781".to_string(),
+                            after_hash: "2ad3c7437786d6625776f0583bc3d6b2".to_string(),
+                            patch: vec![
+                                223, 177, 0, 0, 0, 16, 0, 0, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                                0, 0, 0, 0, 0, 0, 0, 0, 0, 6, 32, 97, 103, 97, 105, 110, 0
+                            ],
+                            block_size: None,
+                            encrypted: false,
+                        })
+                    ),
                 ]),
+                hash_algorithm: HashAlgorithm::Md5,
                 hash_before: v2_hash,
                 operations_hash: "772e8078384f8a99cda819d2d3807864".to_string(),
+                metadata: expected_metadata(&[
+                    "root1.txt",
+                    "parent1/file1.txt",
+                    "parent1/child1/deep3.txt",
+                    "parent3/newfile.txt",
+                    "parent3/another.txt",
+                ]),
+                chunk_store: std::collections::HashMap::new(),
+                fsst_table: None,
             }
         );
 